@@ -0,0 +1,224 @@
+//! Commands sent to sway or Hyprland over their IPC sockets, instead of starting `swaymsg` or
+//! `hyprctl` for each of them
+
+use std::env;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::environment::CommandEnv;
+use crate::executor::CallError;
+use crate::gestures::action::{Format, Values};
+use crate::kdl::ToKdl;
+
+/// Start of every message of the sway IPC protocol
+const SWAY_MAGIC: &[u8] = b"i3-ipc";
+/// Type of a sway message that runs commands
+const SWAY_RUN_COMMAND: u32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compositor {
+    Sway,
+    Hyprland,
+}
+
+impl Compositor {
+    pub fn name(self) -> &'static str {
+        match self {
+            Compositor::Sway => "sway",
+            Compositor::Hyprland => "hyprland",
+        }
+    }
+
+    /// The compositor of the session, as told by the variables of `env` or the daemon's own
+    pub fn detect(env: &CommandEnv) -> Option<Compositor> {
+        let set = |name: &str| env.var(name).is_some() || env::var_os(name).is_some();
+        if set("SWAYSOCK") {
+            Some(Compositor::Sway)
+        } else if set("HYPRLAND_INSTANCE_SIGNATURE") {
+            Some(Compositor::Hyprland)
+        } else {
+            None
+        }
+    }
+
+    /// Command switching to the next workspace, or the previous one if `next` is false
+    pub fn workspace_command(self, next: bool) -> &'static str {
+        match (self, next) {
+            (Compositor::Sway, true) => "workspace next",
+            (Compositor::Sway, false) => "workspace prev",
+            (Compositor::Hyprland, true) => "dispatch workspace e+1",
+            (Compositor::Hyprland, false) => "dispatch workspace e-1",
+        }
+    }
+
+    /// Socket of the running compositor, as told by the variables of `env` or the daemon's own
+    fn socket(self, env: &CommandEnv) -> Result<PathBuf, String> {
+        let var = |name: &str| {
+            env.var(name)
+                .map(str::to_string)
+                .or_else(|| env::var(name).ok())
+        };
+        match self {
+            Compositor::Sway => var("SWAYSOCK")
+                .map(PathBuf::from)
+                .ok_or_else(|| "`SWAYSOCK` is not set".to_string()),
+            Compositor::Hyprland => {
+                let signature = var("HYPRLAND_INSTANCE_SIGNATURE")
+                    .ok_or_else(|| "`HYPRLAND_INSTANCE_SIGNATURE` is not set".to_string())?;
+                // Older releases keep the socket in /tmp
+                let runtime = var("XDG_RUNTIME_DIR")
+                    .map(|dir| Path::new(&dir).join("hypr").join(&signature))
+                    .filter(|dir| dir.is_dir());
+                let dir = runtime.unwrap_or_else(|| Path::new("/tmp/hypr").join(&signature));
+                Ok(dir.join(".socket.sock"))
+            }
+        }
+    }
+}
+
+/// Command given as `end sway="workspace next"` or `end hyprland="dispatch workspace e+1"`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompositorCommand {
+    pub compositor: Compositor,
+    pub command: Format,
+    /// Path of the socket, found from the environment if not given
+    pub socket: Option<String>,
+}
+
+impl CompositorCommand {
+    /// The message with the placeholders replaced by `values`, for the socket found in `env`
+    pub fn render(&self, values: &Values, env: &CommandEnv) -> Result<Message, String> {
+        let socket = match &self.socket {
+            Some(socket) => PathBuf::from(socket),
+            None => self.compositor.socket(env)?,
+        };
+        Ok(Message {
+            compositor: self.compositor,
+            socket,
+            command: self.command.render(values),
+        })
+    }
+
+    /// The command as a KDL node named `name`
+    pub fn node(&self, name: &str) -> String {
+        let mut node = format!(
+            "{name} {}={}",
+            self.compositor.name(),
+            self.command.to_kdl()
+        );
+        if let Some(socket) = &self.socket {
+            node.push_str(&format!(" socket={}", socket.to_kdl()));
+        }
+        node
+    }
+}
+
+impl fmt::Display for CompositorCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.compositor.name(), self.command.source())
+    }
+}
+
+/// A command ready to be sent
+#[derive(Debug)]
+pub struct Message {
+    compositor: Compositor,
+    socket: PathBuf,
+    command: String,
+}
+
+impl Message {
+    /// `command` for the socket of `compositor` found in `env`
+    pub fn new(compositor: Compositor, env: &CommandEnv, command: &str) -> Result<Self, String> {
+        Ok(Self {
+            compositor,
+            socket: compositor.socket(env)?,
+            command: command.to_string(),
+        })
+    }
+}
+
+/// Result of one of the commands of a sway message
+#[derive(Deserialize)]
+struct SwayReply {
+    success: bool,
+    error: Option<String>,
+}
+
+impl Message {
+    /// Send the command and wait up to `timeout` for the reply
+    pub fn send(&self, timeout: Duration) -> Result<(), CallError> {
+        let failed = |e: io::Error| match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => CallError::TimedOut,
+            _ => CallError::Failed(format!("{}: {e}", self.socket.display())),
+        };
+        let mut stream = UnixStream::connect(&self.socket).map_err(failed)?;
+        stream.set_read_timeout(Some(timeout)).map_err(failed)?;
+        stream.set_write_timeout(Some(timeout)).map_err(failed)?;
+        match self.compositor {
+            Compositor::Sway => {
+                stream
+                    .write_all(&sway_message(&self.command))
+                    .map_err(failed)?;
+                let payload = read_sway_reply(&mut stream).map_err(failed)?;
+                let replies: Vec<SwayReply> = serde_yaml::from_slice(&payload)
+                    .map_err(|e| CallError::Reply(format!("unexpected reply: {e}")))?;
+                let errors: Vec<String> = replies
+                    .into_iter()
+                    .filter(|r| !r.success)
+                    .map(|r| r.error.unwrap_or_else(|| "unknown error".to_string()))
+                    .collect();
+                if !errors.is_empty() {
+                    return Err(CallError::Reply(errors.join(", ")));
+                }
+            }
+            Compositor::Hyprland => {
+                stream.write_all(self.command.as_bytes()).map_err(failed)?;
+                stream.shutdown(std::net::Shutdown::Write).map_err(failed)?;
+                let mut reply = String::new();
+                stream.read_to_string(&mut reply).map_err(failed)?;
+                // Batches of commands reply with one "ok" each
+                if reply.split_whitespace().any(|word| word != "ok") {
+                    return Err(CallError::Reply(reply.trim().to_string()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} command `{}`", self.compositor.name(), self.command)
+    }
+}
+
+/// `command` as a sway message that runs it
+pub fn sway_message(command: &str) -> Vec<u8> {
+    let mut message = SWAY_MAGIC.to_vec();
+    message.extend((command.len() as u32).to_ne_bytes());
+    message.extend(SWAY_RUN_COMMAND.to_ne_bytes());
+    message.extend(command.as_bytes());
+    message
+}
+
+/// Read a sway message, returning its payload
+pub fn read_sway_reply(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut header = [0; 14];
+    stream.read_exact(&mut header)?;
+    if &header[..6] != SWAY_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a sway IPC message",
+        ));
+    }
+    let length = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+    let mut payload = vec![0; length as usize];
+    stream.read_exact(&mut payload)?;
+    Ok(payload)
+}
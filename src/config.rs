@@ -1,40 +1,540 @@
-use std::{env, fs, path::Path};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use miette::{bail, IntoDiagnostic, Result};
+use miette::{
+    bail, LabeledSpan, MietteDiagnostic, NamedSource, Report, Result, Severity, SourceSpan, WrapErr,
+};
 // use serde::{Deserialize, Serialize};
-use knuffel::{parse, Decode};
+use knuffel::{
+    ast::{Literal, TypeName},
+    decode::Context,
+    errors::DecodeError,
+    parse_ast, parse_with_context,
+    span::{Span, Spanned},
+    traits::{DecodeSpan, ErrorSpan},
+    Decode, DecodeScalar,
+};
 
-use crate::gestures::Gesture;
+use crate::environment::EnvironmentSettings;
+use crate::executor::ExecutorSettings;
+use crate::gestures::{
+    stroke::{builtin_templates, Template},
+    Gesture,
+};
+use crate::kdl::{NodeWriter, ToKdl};
+use crate::validation;
 
-#[derive(Decode, PartialEq, Debug, Default)]
+#[derive(Decode, PartialEq, Debug, Clone, Default)]
+#[knuffel(span_type = Span)]
 pub struct Config {
     // pub device: Option<String>,
+    /// Command printing the focused window, used instead of asking X11
+    #[knuffel(child, unwrap(argument))]
+    pub window_query: Option<String>,
+    /// Size of the pool running the commands
+    #[knuffel(child, default)]
+    pub executor: ExecutorSettings,
+    /// Variables and working directory of the commands
+    #[knuffel(child, default)]
+    pub environment: EnvironmentSettings,
+    /// Variables for the commands of this file and the files it includes
+    #[knuffel(children(name = "var"))]
+    pub vars: Vec<Var>,
+    /// Other config files merged into this one, resolved when the file is read
+    #[knuffel(children(name = "include"))]
+    pub includes: Vec<Include>,
+    #[knuffel(children(name = "template"))]
+    pub templates: Vec<Template>,
+    /// Gestures that apply to every profile, as do the ones outside of any block
+    #[knuffel(child, default, unwrap(children))]
+    pub global: Vec<Gesture>,
+    #[knuffel(children(name = "profile"))]
+    pub profiles: Vec<Profile>,
     #[knuffel(children)]
     pub gestures: Vec<Gesture>,
+    /// Name of the profile in use, switched at runtime over IPC
+    pub active_profile: String,
+    /// Increased whenever the gestures change at runtime, by a reload or a profile switch, so
+    /// that what was worked out from them can be worked out again
+    pub generation: u64,
 }
 
+/// Named set of gestures, of which only one is active at a time
+#[derive(Decode, PartialEq, Debug, Clone, Default)]
+#[knuffel(span_type = Span)]
+pub struct Profile {
+    #[knuffel(argument)]
+    pub name: String,
+    #[knuffel(children)]
+    pub gestures: Vec<Gesture>,
+}
+
+/// `var name="term" value="alacritty"`, referenced as `${term}` in commands
+#[derive(Decode, PartialEq, Debug, Clone)]
+pub struct Var {
+    #[knuffel(property)]
+    pub name: String,
+    #[knuffel(property)]
+    pub value: String,
+}
+
+/// Variables that commands can reference, expanded when the config is read
+#[derive(Debug, Clone, Default)]
+pub struct Vars(HashMap<String, String>);
+
+impl Vars {
+    /// These variables with `vars` added, replacing those with the same name
+    fn with(&self, vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut all = self.0.clone();
+        all.extend(vars);
+        Self(all)
+    }
+
+    /// Replace `${name}` with the value of a variable and `${env:NAME}` with that of an
+    /// environment variable. `$${` is left as `${`.
+    pub fn expand(&self, s: &str) -> Result<String, String> {
+        let mut expanded = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(i) = rest.find("${") {
+            if rest[..i].ends_with('$') {
+                expanded.push_str(&rest[..i - 1]);
+                expanded.push_str("${");
+                rest = &rest[i + 2..];
+                continue;
+            }
+            expanded.push_str(&rest[..i]);
+            let after = &rest[i + 2..];
+            let Some(end) = after.find('}') else {
+                return Err("`${` is not closed, use `$${` for a literal `${`".to_string());
+            };
+            let name = &after[..end];
+            let value = match name.strip_prefix("env:") {
+                Some(var) => {
+                    env::var(var).map_err(|_| format!("environment variable `{var}` is not set"))?
+                }
+                None => self
+                    .0
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| format!("variable `{name}` is not defined"))?,
+            };
+            expanded.push_str(&value);
+            rest = &after[end + 1..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+}
+
+/// Text with the config's variables expanded, see [`Vars::expand`]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Expanded(pub String);
+
+impl<S: ErrorSpan> DecodeScalar<S> for Expanded {
+    fn type_check(type_name: &Option<Spanned<TypeName, S>>, ctx: &mut Context<S>) {
+        <String as DecodeScalar<S>>::type_check(type_name, ctx)
+    }
+
+    fn raw_decode(
+        value: &Spanned<Literal, S>,
+        ctx: &mut Context<S>,
+    ) -> Result<Self, DecodeError<S>> {
+        let s = <String as DecodeScalar<S>>::raw_decode(value, ctx)?;
+        let expanded = match ctx.get::<Vars>() {
+            Some(vars) => vars.expand(&s),
+            None => Vars::default().expand(&s),
+        };
+        expanded
+            .map(Expanded)
+            .map_err(|e| DecodeError::conversion(value, e))
+    }
+}
+
+impl ToKdl for Expanded {
+    /// Escape what would otherwise be read as a variable again
+    fn to_kdl(&self) -> String {
+        self.0.replace("${", "$${").to_kdl()
+    }
+}
+
+#[derive(Decode, PartialEq, Debug, Clone, Default)]
+pub struct Include {
+    /// Relative paths are resolved against the directory of the including file
+    #[knuffel(argument)]
+    pub path: PathBuf,
+}
+
+impl fmt::Display for Config {
+    /// Write the config as KDL, with includes already merged in
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.active_profile.is_empty() {
+            writeln!(f, "// Active profile: {}", self.active_profile)?;
+        }
+        for var in &self.vars {
+            NodeWriter::new(f, "var")
+                .property("name", Some(&var.name))
+                .property("value", Some(&var.value))
+                .finish()?;
+            writeln!(f)?;
+        }
+        if let Some(query) = &self.window_query {
+            NodeWriter::new(f, "window-query")
+                .argument(query)
+                .finish()?;
+            writeln!(f)?;
+        }
+        if !self.executor.is_empty() {
+            writeln!(f, "{}", self.executor)?;
+        }
+        if !self.environment.is_empty() {
+            writeln!(f, "{}", self.environment)?;
+        }
+        for template in &self.templates {
+            writeln!(f, "{template}")?;
+        }
+        if !self.global.is_empty() {
+            write_block(f, "global", None, &self.global)?;
+        }
+        for gesture in &self.gestures {
+            writeln!(f, "{gesture}")?;
+        }
+        for profile in &self.profiles {
+            write_block(f, "profile", Some(&profile.name), &profile.gestures)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_block(
+    f: &mut fmt::Formatter<'_>,
+    name: &str,
+    argument: Option<&str>,
+    gestures: &[Gesture],
+) -> fmt::Result {
+    let mut node = NodeWriter::new(f, name);
+    if let Some(argument) = argument {
+        node = node.argument(argument);
+    }
+    node.finish()?;
+    writeln!(f, " {{")?;
+    for gesture in gestures {
+        for line in gesture.to_string().lines() {
+            writeln!(f, "    {line}")?;
+        }
+    }
+    writeln!(f, "}}")
+}
+
+/// Text of a config file, kept to point diagnostics at it
+#[derive(Debug, PartialEq)]
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+}
+
+/// Where in the config files a node was defined
+#[derive(Clone, Default, PartialEq)]
+pub struct Origin {
+    pub source: Option<Arc<SourceFile>>,
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl Origin {
+    pub fn span(&self) -> SourceSpan {
+        (self.offset, self.len).into()
+    }
+
+    pub fn named_source(&self) -> Option<NamedSource> {
+        self.source
+            .as_ref()
+            .map(|s| NamedSource::new(&s.name, s.text.clone()))
+    }
+
+    /// Point `diagnostic` at this node
+    pub fn report(&self, diagnostic: MietteDiagnostic) -> Report {
+        match self.named_source() {
+            Some(source) => Report::new(diagnostic.with_label(LabeledSpan::underline(self.span())))
+                .with_source_code(source),
+            None => Report::new(diagnostic),
+        }
+    }
+
+    /// `file:line`, for mentioning a node in another node's diagnostic
+    pub fn location(&self) -> String {
+        match &self.source {
+            Some(s) => {
+                let line = s.text[..self.offset.min(s.text.len())]
+                    .matches('\n')
+                    .count()
+                    + 1;
+                format!("{}:{line}", s.name)
+            }
+            None => "<unknown>".to_string(),
+        }
+    }
+}
+
+impl fmt::Debug for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Origin({}, {}..{})",
+            self.location(),
+            self.offset,
+            self.offset + self.len
+        )
+    }
+}
+
+impl DecodeSpan<Span> for Origin {
+    fn decode_span(span: &Span, ctx: &mut Context<Span>) -> Self {
+        Self {
+            source: ctx.get::<Arc<SourceFile>>().cloned(),
+            offset: span.0,
+            len: span.1 - span.0,
+        }
+    }
+}
+
+/// Profile that is active until another one is selected
+pub const DEFAULT_PROFILE: &str = "default";
+
 impl Config {
     pub fn read_from_file(file: &Path) -> Result<Self> {
+        Self::read_with_includes(file, &mut Vec::new(), &Vars::default())
+    }
+
+    /// Read a config file and the files it includes, with `stack` holding the files that are
+    /// currently being read to catch include cycles and `vars` the variables of those files
+    fn read_with_includes(file: &Path, stack: &mut Vec<PathBuf>, vars: &Vars) -> Result<Self> {
         log::debug!("{:?}", &file);
-        match fs::read_to_string(file) {
-            Ok(s) => Ok(parse::<Config>(file.to_str().unwrap(), &s).into_diagnostic()?),
-            _ => bail!("Could not read config file"),
+        let canonical = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+        if stack.contains(&canonical) {
+            bail!("{} is included recursively", file.display());
         }
+
+        let own = match fs::read_to_string(file) {
+            Ok(text) => Self::parse_with_vars(&file.to_string_lossy(), text, vars)?,
+            Err(e) => bail!("Could not read config file {}: {e}", file.display()),
+        };
+
+        stack.push(canonical);
+        let vars = vars.with(own.vars.iter().map(|v| (v.name.clone(), v.value.clone())));
+        let mut config = Config::default();
+        let dir = file.parent().unwrap_or(Path::new("."));
+        for include in &own.includes {
+            let path = dir.join(&include.path);
+            let included = Self::read_with_includes(&path, stack, &vars)
+                .wrap_err_with(|| format!("Included from {}", file.display()))?;
+            config.merge(included);
+        }
+        stack.pop();
+
+        // The including file comes last, so its settings take precedence
+        config.merge(own);
+        Ok(config)
     }
 
+    /// Parse the text of a single config file, without resolving its includes
+    pub fn parse(name: &str, text: String) -> Result<Self> {
+        Self::parse_with_vars(name, text, &Vars::default())
+    }
+
+    /// Parse a config file whose commands can use `vars` along with its own variables
+    fn parse_with_vars(name: &str, text: String, vars: &Vars) -> Result<Self> {
+        // The variables are needed before the commands using them are decoded
+        let ast = parse_ast::<Span>(name, &text).map_err(Report::new)?;
+        let vars = vars.with(
+            ast.nodes
+                .iter()
+                .filter(|n| &**n.node_name == "var")
+                .filter_map(|n| {
+                    let property = |key: &str| {
+                        n.properties.iter().find(|(k, _)| ****k == *key).and_then(
+                            |(_, v)| match &*v.literal {
+                                Literal::String(s) => Some(s.to_string()),
+                                _ => None,
+                            },
+                        )
+                    };
+                    Some((property("name")?, property("value")?))
+                }),
+        );
+
+        let source = Arc::new(SourceFile {
+            name: name.to_string(),
+            text,
+        });
+        let config: Self = parse_with_context(name, &source.text, |ctx| {
+            ctx.set(source.clone());
+            ctx.set(vars);
+        })
+        .map_err(Report::new)?;
+        Ok(config)
+    }
+
+    /// Read the first config file found in the user's and then the system's config directories,
+    /// followed by the user's drop-in files
     pub fn read_default_config() -> Result<Self> {
-        let config_home = env::var("XDG_CONFIG_HOME")
-            .unwrap_or_else(|_| format!("{}/.config", env::var("HOME").unwrap()));
+        let (user_dir, system_dirs) = config_dirs(|name| env::var_os(name));
+        Self::read_from_dirs(user_dir, system_dirs)
+    }
 
-        log::debug!("{:?}", &config_home);
+    /// Like [`Config::read_default_config`], with the directories given by [`config_dirs`]
+    pub fn read_from_dirs(user_dir: Option<PathBuf>, system_dirs: Vec<PathBuf>) -> Result<Self> {
+        let dirs: Vec<PathBuf> = user_dir.iter().cloned().chain(system_dirs).collect();
+        log::debug!("{:?}", &dirs);
 
-        for path in ["gestures.kdl", "gestures/gestures.kdl"] {
-            match Self::read_from_file(Path::new(&format!("{config_home}/{path}"))) {
-                Ok(s) => return Ok(s),
-                Err(e) => log::warn!("{}", e),
+        let candidates: Vec<PathBuf> = dirs
+            .iter()
+            .flat_map(|d| CONFIG_PATHS.iter().map(move |p| d.join(p)))
+            .collect();
+        let mut config = match candidates.iter().find(|p| p.is_file()) {
+            Some(path) => Some(Self::read_from_file(path)?),
+            None => None,
+        };
+
+        let drop_in_dir = user_dir.map(|d| d.join("gestures/conf.d"));
+        let drop_ins = drop_in_dir
+            .as_deref()
+            .map(drop_in_files)
+            .unwrap_or_default();
+        if config.is_none() && drop_ins.is_empty() {
+            let tried = candidates
+                .iter()
+                .chain(&drop_in_dir)
+                .map(|p| format!("\n  {}", p.display()))
+                .collect::<String>();
+            bail!(
+                help = format!("Looked in:{tried}"),
+                "Could not find a config file"
+            );
+        }
+
+        // Drop-in files can use the variables of the files read before them
+        for file in drop_ins {
+            let config = config.get_or_insert_with(Config::default);
+            let vars = Vars::default().with(
+                config
+                    .vars
+                    .iter()
+                    .map(|v| (v.name.clone(), v.value.clone())),
+            );
+            config.merge(Self::read_with_includes(&file, &mut Vec::new(), &vars)?);
+        }
+        Ok(config.unwrap_or_default())
+    }
+
+    /// Log the problems found by validating the config, failing if any of them is an error
+    pub fn check(self) -> Result<Self> {
+        let reports = validation::validate(&self);
+        let errors = validation::has_errors(&reports);
+        for report in reports {
+            match report.severity() {
+                Some(Severity::Warning | Severity::Advice) => log::warn!("{report:?}"),
+                _ => log::error!("{report:?}"),
+            }
+        }
+        if errors {
+            bail!("The configuration has errors, see above");
+        }
+        Ok(self)
+    }
+
+    /// Merge another config into this one. Gestures are added to the existing ones, while
+    /// settings and templates from `other` replace those already set.
+    pub fn merge(&mut self, other: Config) {
+        if other.window_query.is_some() {
+            self.window_query = other.window_query;
+        }
+        self.executor.merge(other.executor);
+        self.environment.merge(other.environment);
+        for var in other.vars {
+            self.vars.retain(|v| v.name != var.name);
+            self.vars.push(var);
+        }
+        for template in other.templates {
+            self.templates.retain(|t| t.name != template.name);
+            self.templates.push(template);
+        }
+        self.global.extend(other.global);
+        for profile in other.profiles {
+            match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+                Some(p) => p.gestures.extend(profile.gestures),
+                None => self.profiles.push(profile),
             }
         }
+        self.gestures.extend(other.gestures);
+    }
+
+    /// Gestures in effect: the global ones followed by those of the active profile
+    pub fn active_gestures(&self) -> impl Iterator<Item = &Gesture> {
+        let active = self.active_profile();
+        self.global.iter().chain(&self.gestures).chain(
+            self.profiles
+                .iter()
+                .filter(move |p| p.name == active)
+                .flat_map(|p| &p.gestures),
+        )
+    }
+
+    pub fn active_profile(&self) -> &str {
+        match self.active_profile.as_str() {
+            "" => DEFAULT_PROFILE,
+            name => name,
+        }
+    }
 
-        bail!("Could not find config file")
+    /// Templates defined in the config, followed by the builtin ones they don't override
+    pub fn stroke_templates(&self) -> Vec<Template> {
+        let mut templates = self.templates.clone();
+        templates.extend(
+            builtin_templates()
+                .into_iter()
+                .filter(|b| !self.templates.iter().any(|t| t.name == b.name)),
+        );
+        templates
     }
 }
+
+/// Config file names looked for in each config directory, in order
+const CONFIG_PATHS: [&str; 2] = ["gestures.kdl", "gestures/gestures.kdl"];
+
+/// The user's config directory and the system's ones, from `XDG_CONFIG_HOME`, `HOME` and
+/// `XDG_CONFIG_DIRS` as looked up by `var`. Empty variables count as unset.
+pub fn config_dirs(var: impl Fn(&str) -> Option<OsString>) -> (Option<PathBuf>, Vec<PathBuf>) {
+    let var = |name| var(name).filter(|v: &OsString| !v.is_empty());
+    let user_dir = match (var("XDG_CONFIG_HOME"), var("HOME")) {
+        (Some(dir), _) => Some(PathBuf::from(dir)),
+        (None, Some(home)) => Some(PathBuf::from(home).join(".config")),
+        (None, None) => {
+            log::warn!("Neither XDG_CONFIG_HOME nor HOME is set");
+            None
+        }
+    };
+    let system_dirs = var("XDG_CONFIG_DIRS").unwrap_or_else(|| "/etc/xdg".into());
+    let system_dirs = env::split_paths(&system_dirs)
+        .filter(|d| d.is_absolute())
+        .collect();
+    (user_dir, system_dirs)
+}
+
+/// The `*.kdl` files in a drop-in directory, in the order they are merged
+fn drop_in_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "kdl"))
+        .collect();
+    files.sort();
+    files
+}
@@ -0,0 +1,267 @@
+//! Methods called over D-Bus by gestures, from the daemon itself rather than by starting a
+//! process like `dbus-send`
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use async_io::Timer;
+use futures_lite::future;
+
+use zbus::blocking::{connection::Builder, Connection};
+use zbus::zvariant::{ObjectPath, Signature, StructureBuilder, Value};
+
+use crate::environment::CommandEnv;
+use crate::executor::CallError;
+use crate::gestures::action::{Format, Values};
+use crate::kdl::ToKdl;
+
+/// Type codes an argument can be converted to
+const ARGUMENT_TYPES: &str = "sogbynqiuxtd";
+
+/// Connections shared by all calls, by bus
+static CONNECTIONS: OnceLock<Mutex<HashMap<String, Connection>>> = OnceLock::new();
+
+/// Message bus to call a method on
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bus {
+    Session,
+    System,
+    /// Any other bus, by its address, like `unix:path=/run/user/1000/bus`
+    Address(String),
+}
+
+impl Bus {
+    fn name(&self) -> &str {
+        match self {
+            Bus::Session => "session",
+            Bus::System => "system",
+            Bus::Address(address) => address,
+        }
+    }
+}
+
+impl From<&str> for Bus {
+    fn from(s: &str) -> Self {
+        match s {
+            "session" => Bus::Session,
+            "system" => Bus::System,
+            address => Bus::Address(address.to_string()),
+        }
+    }
+}
+
+/// Method call given as a command, like
+/// `end destination="org.mpris.MediaPlayer2.spotify" path="/org/mpris/MediaPlayer2"
+/// interface="org.mpris.MediaPlayer2.Player" method="Next"`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DBusCall {
+    pub bus: Bus,
+    pub destination: String,
+    pub path: String,
+    pub interface: Option<String>,
+    pub method: String,
+    /// Type code of each argument, `s` for all of them if not given
+    pub signature: Option<String>,
+    pub args: Vec<Format>,
+}
+
+impl DBusCall {
+    /// Check that the signature fits the arguments
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(signature) = &self.signature {
+            if let Some(c) = signature.chars().find(|c| !ARGUMENT_TYPES.contains(*c)) {
+                return Err(format!(
+                    "`{c}` is not a type an argument can have, use one of `{ARGUMENT_TYPES}`"
+                ));
+            }
+            if signature.len() != self.args.len() {
+                return Err(format!(
+                    "the signature has {} types for {} arguments",
+                    signature.len(),
+                    self.args.len()
+                ));
+            }
+        }
+        ObjectPath::try_from(self.path.as_str())
+            .map_err(|_| format!("`{}` is not an object path", self.path))?;
+        Ok(())
+    }
+
+    /// The call with the placeholders of the arguments replaced by `values`, on the session bus
+    /// of `env` if it sets one
+    pub fn render(&self, values: &Values, env: &CommandEnv) -> Result<Call, String> {
+        let signature = self.signature.as_deref().unwrap_or("");
+        let mut body = StructureBuilder::new();
+        for (i, arg) in self.args.iter().enumerate() {
+            let code = signature.chars().nth(i).unwrap_or('s');
+            body.push_value(argument(code, arg.render(values))?);
+        }
+        let bus = match (&self.bus, env.var("DBUS_SESSION_BUS_ADDRESS")) {
+            (Bus::Session, Some(address)) => Bus::Address(address.to_string()),
+            (bus, _) => bus.clone(),
+        };
+        Ok(Call {
+            bus,
+            destination: self.destination.clone(),
+            path: self.path.clone(),
+            interface: self.interface.clone(),
+            method: self.method.clone(),
+            body: if self.args.is_empty() {
+                None
+            } else {
+                Some(body.build().map_err(|e| e.to_string())?)
+            },
+        })
+    }
+
+    /// The call as a KDL node named `name`
+    pub fn node(&self, name: &str) -> String {
+        let mut node = name.to_string();
+        let properties = [
+            ("bus", (self.bus != Bus::Session).then(|| self.bus.name())),
+            ("destination", Some(self.destination.as_str())),
+            ("path", Some(self.path.as_str())),
+            ("interface", self.interface.as_deref()),
+            ("method", Some(self.method.as_str())),
+            ("signature", self.signature.as_deref()),
+        ];
+        for (property, value) in properties {
+            if let Some(value) = value {
+                node.push_str(&format!(" {property}={}", value.to_kdl()));
+            }
+        }
+        for arg in &self.args {
+            node.push(' ');
+            node.push_str(&arg.to_kdl());
+        }
+        node
+    }
+}
+
+impl fmt::Display for DBusCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.node("dbus"))
+    }
+}
+
+/// `value` converted to the D-Bus type `code`
+fn argument(code: char, value: String) -> Result<Value<'static>, String> {
+    let invalid = |e: &dyn fmt::Display| format!("`{value}` is not a valid `{code}`: {e}");
+    Ok(match code {
+        's' => Value::from(value),
+        'o' => Value::from(ObjectPath::try_from(value.clone()).map_err(|e| invalid(&e))?),
+        'g' => Value::from(Signature::try_from(value.as_str()).map_err(|e| invalid(&e))?),
+        'b' => Value::from(value.parse::<bool>().map_err(|e| invalid(&e))?),
+        'y' => Value::from(value.parse::<u8>().map_err(|e| invalid(&e))?),
+        'n' => Value::from(value.parse::<i16>().map_err(|e| invalid(&e))?),
+        'q' => Value::from(value.parse::<u16>().map_err(|e| invalid(&e))?),
+        'i' => Value::from(value.parse::<i32>().map_err(|e| invalid(&e))?),
+        'u' => Value::from(value.parse::<u32>().map_err(|e| invalid(&e))?),
+        'x' => Value::from(value.parse::<i64>().map_err(|e| invalid(&e))?),
+        't' => Value::from(value.parse::<u64>().map_err(|e| invalid(&e))?),
+        'd' => Value::from(value.parse::<f64>().map_err(|e| invalid(&e))?),
+        _ => return Err(format!("`{code}` is not a type an argument can have")),
+    })
+}
+
+/// A method call ready to be sent
+#[derive(Debug)]
+pub struct Call {
+    bus: Bus,
+    destination: String,
+    path: String,
+    interface: Option<String>,
+    method: String,
+    body: Option<zbus::zvariant::Structure<'static>>,
+}
+
+impl Call {
+    /// Call the method and wait up to `timeout` for its reply
+    pub fn send(&self, timeout: Duration) -> Result<(), CallError> {
+        let key = self.bus.name().to_string();
+        let connections = CONNECTIONS.get_or_init(Default::default);
+        let cached = connections.lock().unwrap().get(&key).cloned();
+        let connection = match cached {
+            Some(connection) => connection,
+            None => {
+                let connection = self.connect().map_err(CallError::Failed)?;
+                connections
+                    .lock()
+                    .unwrap()
+                    .insert(key.clone(), connection.clone());
+                connection
+            }
+        };
+
+        let connection = connection.inner();
+        let call = async {
+            match &self.body {
+                Some(body) => {
+                    connection
+                        .call_method(
+                            Some(self.destination.as_str()),
+                            self.path.as_str(),
+                            self.interface.as_deref(),
+                            self.method.as_str(),
+                            body,
+                        )
+                        .await
+                }
+                None => {
+                    connection
+                        .call_method(
+                            Some(self.destination.as_str()),
+                            self.path.as_str(),
+                            self.interface.as_deref(),
+                            self.method.as_str(),
+                            &(),
+                        )
+                        .await
+                }
+            }
+        };
+        let reply = zbus::block_on(future::or(call, async {
+            Timer::after(timeout).await;
+            Err(zbus::Error::InputOutput(Arc::new(
+                std::io::ErrorKind::TimedOut.into(),
+            )))
+        }));
+        match reply {
+            Ok(_) => Ok(()),
+            Err(zbus::Error::MethodError(name, description, _)) => Err(CallError::Reply(
+                description.map_or(name.to_string(), |d| format!("{name}: {d}")),
+            )),
+            Err(zbus::Error::InputOutput(e)) if e.kind() == std::io::ErrorKind::TimedOut => {
+                Err(CallError::TimedOut)
+            }
+            Err(e) => {
+                // The bus may have gone away, so connect again for the next call
+                connections.lock().unwrap().remove(&key);
+                Err(CallError::Failed(e.to_string()))
+            }
+        }
+    }
+
+    fn connect(&self) -> Result<Connection, String> {
+        let builder = match &self.bus {
+            Bus::Session => Builder::session(),
+            Bus::System => Builder::system(),
+            Bus::Address(address) => Builder::address(address.as_str()),
+        };
+        builder
+            .and_then(|b| b.build())
+            .map_err(|e| format!("could not connect to the {} bus: {e}", self.bus.name()))
+    }
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "D-Bus call of {}", self.destination)?;
+        match &self.interface {
+            Some(interface) => write!(f, " {}.{}", interface, self.method),
+            None => write!(f, " {}", self.method),
+        }
+    }
+}
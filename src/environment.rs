@@ -0,0 +1,232 @@
+//! Environment of the commands run by gestures: variables and a working directory from the
+//! config, and the variables of the graphical session, which a daemon started before the
+//! compositor doesn't have
+
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use knuffel::{span::Span, Decode};
+
+use crate::config::{Expanded, Origin};
+use crate::kdl::NodeWriter;
+
+/// Variables of the graphical session that are imported from the systemd user manager
+pub const SESSION_VARS: [&str; 13] = [
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "XDG_CURRENT_DESKTOP",
+    "XDG_SESSION_DESKTOP",
+    "XDG_SESSION_TYPE",
+    "DESKTOP_SESSION",
+    "SWAYSOCK",
+    "I3SOCK",
+    "HYPRLAND_INSTANCE_SIGNATURE",
+    "NIRI_SOCKET",
+    "KDE_FULL_SESSION",
+];
+/// How long the imported session variables are used before they are read again, since the
+/// session may export them only after the daemon started
+const SESSION_REFRESH: Duration = Duration::from_secs(10);
+
+/// `environment import-session=true working-directory="..." { NAME "value"; }`
+#[derive(Decode, PartialEq, Debug, Clone, Default)]
+#[knuffel(span_type = Span)]
+pub struct EnvironmentSettings {
+    /// Whether to add the variables of the graphical session, as known to `systemctl --user`
+    #[knuffel(property)]
+    pub import_session: Option<bool>,
+    #[knuffel(property)]
+    pub working_directory: Option<Expanded>,
+    #[knuffel(children)]
+    pub vars: Vec<EnvVar>,
+    #[knuffel(span)]
+    pub origin: Origin,
+}
+
+/// Variable set for every command, named by the node
+#[derive(Decode, PartialEq, Debug, Clone)]
+#[knuffel(span_type = Span)]
+pub struct EnvVar {
+    #[knuffel(node_name)]
+    pub name: String,
+    #[knuffel(argument)]
+    pub value: Expanded,
+    #[knuffel(span)]
+    pub origin: Origin,
+}
+
+impl EnvironmentSettings {
+    pub fn is_empty(&self) -> bool {
+        self.import_session.is_none() && self.working_directory.is_none() && self.vars.is_empty()
+    }
+
+    /// The settings of `other` where it has any, these otherwise. Variables are added, replacing
+    /// those with the same name.
+    pub fn merge(&mut self, other: EnvironmentSettings) {
+        if !other.is_empty() {
+            self.origin = other.origin;
+        }
+        self.import_session = other.import_session.or(self.import_session);
+        self.working_directory = other.working_directory.or(self.working_directory.take());
+        for var in other.vars {
+            self.vars.retain(|v| v.name != var.name);
+            self.vars.push(var);
+        }
+    }
+
+    /// The environment to run commands with, taking the session's variables from `session`
+    pub fn resolve(&self, session: &Session) -> CommandEnv {
+        let mut vars = Vec::new();
+        if self.import_session == Some(true) {
+            vars = session.vars();
+        }
+        for var in &self.vars {
+            vars.retain(|(name, _)| *name != var.name);
+            vars.push((var.name.clone(), var.value.0.clone()));
+        }
+        CommandEnv {
+            vars,
+            working_directory: self.working_directory.as_ref().map(|d| PathBuf::from(&d.0)),
+        }
+    }
+}
+
+impl fmt::Display for EnvironmentSettings {
+    /// Write the settings as a KDL node
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut node = NodeWriter::new(f, "environment")
+            .property("import-session", self.import_session.as_ref())
+            .property("working-directory", self.working_directory.as_ref());
+        for var in &self.vars {
+            node = node.child(&var.name, std::slice::from_ref(&var.value));
+        }
+        node.finish()
+    }
+}
+
+/// Variables and working directory applied to every command, resolved when a gesture begins
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandEnv {
+    pub vars: Vec<(String, String)>,
+    pub working_directory: Option<PathBuf>,
+}
+
+impl CommandEnv {
+    /// Value of the variable `name`, if it is set here
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.vars
+            .iter()
+            .rfind(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn apply(&self, command: &mut Command) {
+        command.envs(self.vars.iter().map(|(k, v)| (k, v)));
+        if let Some(dir) = &self.working_directory {
+            command.current_dir(dir);
+        }
+    }
+}
+
+/// The graphical session's variables, read from the systemd user manager, which also holds the
+/// D-Bus activation environment when the session exports it with
+/// `dbus-update-activation-environment --systemd`. They are read on a thread of their own, so
+/// that a slow user manager doesn't hold up gestures.
+#[derive(Debug, Default, Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    vars: Vec<(String, String)>,
+    read_at: Option<Instant>,
+    reading: bool,
+    failed: bool,
+}
+
+impl Session {
+    /// The variables read last, which are read again in the background if they are older than
+    /// [`SESSION_REFRESH`]
+    pub fn vars(&self) -> Vec<(String, String)> {
+        let mut state = self.state.lock().unwrap();
+        let stale = state
+            .read_at
+            .map_or(true, |t| t.elapsed() > SESSION_REFRESH);
+        if !state.reading && stale {
+            state.reading = true;
+            let session = self.clone();
+            thread::spawn(move || session.read());
+        }
+        state.vars.clone()
+    }
+
+    fn read(&self) {
+        let result = Command::new("systemctl")
+            .args(["--user", "show-environment"])
+            .output();
+        let mut state = self.state.lock().unwrap();
+        state.reading = false;
+        state.read_at = Some(Instant::now());
+        match result {
+            Ok(output) if output.status.success() => {
+                state.vars = parse_show_environment(&String::from_utf8_lossy(&output.stdout));
+                state.failed = false;
+            }
+            result => {
+                // Only log once rather than on every gesture
+                if !state.failed {
+                    let reason = match result {
+                        Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                        Err(e) => e.to_string(),
+                    };
+                    log::warn!("Could not import the session environment: {reason}");
+                }
+                state.failed = true;
+            }
+        }
+        log::debug!("Session environment: {:?}", state.vars);
+    }
+}
+
+/// The [`SESSION_VARS`] in the output of `systemctl --user show-environment`, which writes
+/// `NAME=value` lines and quotes values with special characters as `$'...'`
+pub fn parse_show_environment(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .filter_map(|line| line.split_once('='))
+        .filter(|(name, _)| SESSION_VARS.contains(name))
+        .map(|(name, value)| {
+            let value = match value.strip_prefix("$'").and_then(|v| v.strip_suffix('\'')) {
+                Some(quoted) => unquote(quoted),
+                None => value.to_string(),
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// Undo the escapes of a `$'...'` string
+fn unquote(s: &str) -> String {
+    let mut unquoted = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unquoted.push('\n'),
+            Some('t') => unquoted.push('\t'),
+            Some('r') => unquoted.push('\r'),
+            Some(c) => unquoted.push(c),
+            None => unquoted.push('\\'),
+        }
+    }
+    unquoted
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     fs::OpenOptions,
     os::{
         fd::{AsFd, OwnedFd},
@@ -14,7 +15,8 @@ use input::{
             GestureEndEvent, GestureEventCoordinates, GestureEventTrait, GestureHoldEvent,
             GesturePinchEvent, GesturePinchEventTrait, GestureSwipeEvent,
         },
-        Event, EventTrait, GestureEvent,
+        pointer::ButtonState,
+        Event, EventTrait, GestureEvent, PointerEvent,
     },
     DeviceCapability, Libinput, LibinputInterface,
 };
@@ -24,22 +26,81 @@ use nix::{
     poll::{poll, PollFd, PollFlags},
 };
 
-use crate::config::Config;
-use crate::gestures::{hold::*, pinch::*, swipe::*, *};
+use crate::compositor::{Compositor, Message};
+use crate::config::{Config, Origin};
+use crate::environment::{CommandEnv, Session};
+use crate::executor::{Executor, Job, Key};
+use crate::gestures::{
+    action::{Action, Values},
+    hold::*,
+    pinch::*,
+    stroke::*,
+    swipe::*,
+    *,
+};
+use crate::pipe::Pipes;
+use crate::window::{WindowFilter, WindowInfo, WindowQuery};
 use crate::xdo_handler::XDoHandler;
-use crate::utils::exec_command_from_string;
+
+/// Most `repeat-every` steps run for a single event, so that a jump in the reported position
+/// can't flood the executor
+const MAX_REPEAT_STEPS: u64 = 8;
 
 #[derive(Debug)]
 pub struct EventHandler {
     config: Arc<RwLock<Config>>,
     event: Gesture,
+    /// Accumulated position of every update of the current swipe, used for strokes
+    path: Vec<(f64, f64)>,
+    /// Timestamp (ms) of the begin event of the current swipe
+    start_time: u32,
+    /// Timestamp (µs) of the last event of the current swipe
+    last_time: u64,
+    /// Motion of the pointer, by index of the dragging swipe in the config
+    drags: HashMap<usize, DragMotion>,
+    /// Net number of `repeat-every` steps fired so far, by index of the swipe in the config
+    repeat_steps: HashMap<usize, i64>,
+    /// Whether the window focused when the current gesture began passes each gesture's filter
+    window_matches: Vec<bool>,
+    /// The focused window when the current gesture began
+    window: Option<WindowInfo>,
+    window_query: WindowQuery,
+    /// Generation of the config the gesture indices of `window_matches` and of the keys of
+    /// commands refer to
+    generation: u64,
+    executor: Executor,
+    pipes: Pipes,
+    /// Environment of the commands of the current gesture
+    env: CommandEnv,
+    session: Session,
 }
 
 impl EventHandler {
-    pub fn new(config: Arc<RwLock<Config>>) -> Self {
+    pub fn new(config: Arc<RwLock<Config>>, executor: Executor, pipes: Pipes) -> Self {
+        let session = Session::default();
+        if config.read().unwrap().environment.import_session == Some(true) {
+            // Read ahead, so that the first gesture already has the variables
+            session.vars();
+        }
+        let window_query = WindowQuery::default();
+        // Likewise, so that the first gesture already knows the window
+        window_query.window(query_command(&config.read().unwrap()));
         Self {
             config,
             event: Gesture::None,
+            path: Vec::new(),
+            start_time: 0,
+            last_time: 0,
+            drags: HashMap::new(),
+            repeat_steps: HashMap::new(),
+            window_matches: Vec::new(),
+            window: None,
+            window_query,
+            generation: 0,
+            executor,
+            pipes,
+            env: CommandEnv::default(),
+            session,
         }
     }
 
@@ -91,40 +152,65 @@ impl EventHandler {
     pub fn handle_event(&mut self, input: &mut Libinput, xdoh: &mut XDoHandler) -> Result<()> {
         input.dispatch().unwrap();
         for event in input.clone() {
-            if let Event::Gesture(e) = event {
-                match e {
-                    GestureEvent::Pinch(e) => self.handle_pinch_event(e)?,
-                    GestureEvent::Swipe(e) => self.handle_swipe_event(e, xdoh)?,
-                    GestureEvent::Hold(e) => self.handle_hold_event(e)?,
-                    _ => (),
+            match event {
+                Event::Gesture(e) => {
+                    self.sync_window();
+                    match e {
+                        GestureEvent::Pinch(e) => self.handle_pinch_event(e, xdoh)?,
+                        GestureEvent::Swipe(e) => self.handle_swipe_event(e, xdoh)?,
+                        GestureEvent::Hold(e) => self.handle_hold_event(e, xdoh)?,
+                        _ => (),
+                    }
                 }
+                Event::Pointer(PointerEvent::Button(e)) => {
+                    xdoh.button_event(
+                        e.button_state() == ButtonState::Pressed,
+                        e.device().has_capability(DeviceCapability::Gesture),
+                    );
+                }
+                _ => (),
             }
             input.dispatch().unwrap();
         }
         Ok(())
     }
 
-    fn handle_hold_event(&mut self, event: GestureHoldEvent) -> Result<()> {
+    fn handle_hold_event(&mut self, event: GestureHoldEvent, xdoh: &mut XDoHandler) -> Result<()> {
         match event {
             GestureHoldEvent::Begin(e) => {
+                xdoh.release_lock();
+                self.begin(xdoh);
                 self.event = Gesture::Hold(Hold {
                     fingers: e.finger_count(),
                     action: None,
+                    timeout: None,
+                    window: WindowFilter::default(),
+                    origin: Origin::default(),
                 })
             }
             GestureHoldEvent::End(_e) => {
                 if let Gesture::Hold(s) = &self.event {
                     log::debug!("Hold: {:?}", &s.fingers);
-                    for i in &self.config.clone().read().unwrap().gestures {
+                    for (n, i) in self
+                        .config
+                        .clone()
+                        .read()
+                        .unwrap()
+                        .active_gestures()
+                        .enumerate()
+                    {
                         if let Gesture::Hold(j) = i {
-                            if j.fingers == s.fingers {
-                                exec_command_from_string(
-                                    &j.action.clone().unwrap_or_default(),
-                                    0.0,
-                                    0.0,
-                                    0.0,
-                                    0.0,
-                                )?;
+                            if j.fingers == s.fingers && self.window_allows(n) {
+                                self.run(
+                                    (n, "action"),
+                                    j.action.as_ref(),
+                                    j.timeout,
+                                    &Values {
+                                        kind: "hold",
+                                        fingers: s.fingers,
+                                        ..Default::default()
+                                    },
+                                );
                             }
                         }
                     }
@@ -135,29 +221,51 @@ impl EventHandler {
         Ok(())
     }
 
-    fn handle_pinch_event(&mut self, event: GesturePinchEvent) -> Result<()> {
+    fn handle_pinch_event(
+        &mut self,
+        event: GesturePinchEvent,
+        xdoh: &mut XDoHandler,
+    ) -> Result<()> {
         match event {
             GesturePinchEvent::Begin(e) => {
+                xdoh.release_lock();
+                self.begin(xdoh);
                 self.event = Gesture::Pinch(Pinch {
                     fingers: e.finger_count(),
                     direction: PinchDir::Any,
                     update: None,
                     start: None,
                     end: None,
+                    max_rate: None,
+                    pipe: None,
+                    timeout: None,
+                    window: WindowFilter::default(),
+                    origin: Origin::default(),
                 });
                 if let Gesture::Pinch(s) = &self.event {
-                    for i in &self.config.clone().read().unwrap().gestures {
+                    for (n, i) in self
+                        .config
+                        .clone()
+                        .read()
+                        .unwrap()
+                        .active_gestures()
+                        .enumerate()
+                    {
                         if let Gesture::Pinch(j) = i {
                             if (j.direction == s.direction || j.direction == PinchDir::Any)
                                 && j.fingers == s.fingers
+                                && self.window_allows(n)
                             {
-                                exec_command_from_string(
-                                    &j.start.clone().unwrap_or_default(),
-                                    0.0,
-                                    0.0,
-                                    0.0,
-                                    0.0,
-                                )?;
+                                let values = Values {
+                                    kind: "pinch",
+                                    fingers: s.fingers,
+                                    direction: "any",
+                                    ..Default::default()
+                                };
+                                self.run((n, "start"), j.start.as_ref(), j.timeout, &values);
+                                if let Some(pipe) = &j.pipe {
+                                    self.pipes.send(pipe, "start", &values, &self.env);
+                                }
                             }
                         }
                     }
@@ -175,18 +283,37 @@ impl EventHandler {
                         &dir,
                         &s.fingers
                     );
-                    for i in &self.config.clone().read().unwrap().gestures {
+                    for (n, i) in self
+                        .config
+                        .clone()
+                        .read()
+                        .unwrap()
+                        .active_gestures()
+                        .enumerate()
+                    {
                         if let Gesture::Pinch(j) = i {
                             if (j.direction == dir || j.direction == PinchDir::Any)
                                 && j.fingers == s.fingers
+                                && self.window_allows(n)
                             {
-                                exec_command_from_string(
-                                    &j.update.clone().unwrap_or_default(),
-                                    0.0,
-                                    0.0,
-                                    delta_angle,
+                                let values = Values {
+                                    kind: "pinch",
                                     scale,
-                                )?;
+                                    delta_angle,
+                                    fingers: s.fingers,
+                                    direction: dir.name(),
+                                    ..Default::default()
+                                };
+                                self.run_update(
+                                    n,
+                                    j.update.as_ref(),
+                                    j.max_rate,
+                                    j.timeout,
+                                    &values,
+                                );
+                                if let Some(pipe) = &j.pipe {
+                                    self.pipes.send(pipe, "update", &values, &self.env);
+                                }
                             }
                         }
                     }
@@ -196,23 +323,39 @@ impl EventHandler {
                         update: None,
                         start: None,
                         end: None,
+                        max_rate: None,
+                        pipe: None,
+                        timeout: None,
+                        window: WindowFilter::default(),
+                        origin: Origin::default(),
                     })
                 }
             }
             GesturePinchEvent::End(_e) => {
                 if let Gesture::Pinch(s) = &self.event {
-                    for i in &self.config.clone().read().unwrap().gestures {
+                    for (n, i) in self
+                        .config
+                        .clone()
+                        .read()
+                        .unwrap()
+                        .active_gestures()
+                        .enumerate()
+                    {
                         if let Gesture::Pinch(j) = i {
                             if (j.direction == s.direction || j.direction == PinchDir::Any)
                                 && j.fingers == s.fingers
+                                && self.window_allows(n)
                             {
-                                exec_command_from_string(
-                                    &j.end.clone().unwrap_or_default(),
-                                    0.0,
-                                    0.0,
-                                    0.0,
-                                    0.0,
-                                )?;
+                                let values = Values {
+                                    kind: "pinch",
+                                    fingers: s.fingers,
+                                    direction: s.direction.name(),
+                                    ..Default::default()
+                                };
+                                self.run((n, "end"), j.end.as_ref(), j.timeout, &values);
+                                if let Some(pipe) = &j.pipe {
+                                    self.pipes.send(pipe, "end", &values, &self.env);
+                                }
                             }
                         }
                     }
@@ -230,119 +373,575 @@ impl EventHandler {
     ) -> Result<()> {
         match event {
             GestureSwipeEvent::Begin(e) => {
-                self.event = Gesture::Swipe(Swipe {
+                self.begin(xdoh);
+                self.event = Gesture::Swipe(Box::new(Swipe {
                     direction: SwipeDir::Any,
                     fingers: e.finger_count(),
-                    update: None,
-                    start: None,
-                    end: None,
-                    acceleration: None,
-                    mouse_up_delay: None,
-                });
+                    ..Default::default()
+                }));
+                self.path = vec![(0.0, 0.0)];
+                self.start_time = e.time();
+                self.last_time = e.time_usec();
+                self.drags.clear();
+                self.repeat_steps.clear();
+                let mut dragging = false;
                 if let Gesture::Swipe(s) = &self.event {
-                    for gesture in &self.config.clone().read().unwrap().gestures {
+                    for (n, gesture) in self
+                        .config
+                        .clone()
+                        .read()
+                        .unwrap()
+                        .active_gestures()
+                        .enumerate()
+                    {
                         if let Gesture::Swipe(j) = gesture {
-                            if j.fingers == s.fingers {
-                                let is_xorg_condition = xdoh.is_xorg
-                                    && j.acceleration.is_some()
-                                    && j.mouse_up_delay.is_some()
-                                    && j.direction == SwipeDir::Any;
+                            if j.fingers == s.fingers && self.window_allows(n) {
+                                let is_xorg_condition = xdoh.is_xorg && j.is_drag();
                                 if is_xorg_condition {
                                     log::debug!("Call libxdo api directly in Xorg env for better performance.");
-                                    xdoh.mouse_down(1);
+                                    xdoh.mouse_down(j.drag_button());
+                                    dragging = true;
                                 } else if j.direction == s.direction || j.direction == SwipeDir::Any
                                 {
-                                    exec_command_from_string(
-                                        &j.start.as_ref().unwrap_or(&String::new()),
-                                        0.0,
-                                        0.0,
-                                        0.0,
-                                        0.0,
-                                    )?;
+                                    let values = Values {
+                                        kind: "swipe",
+                                        fingers: s.fingers,
+                                        direction: "any",
+                                        ..Default::default()
+                                    };
+                                    self.run((n, "start"), j.start.as_ref(), j.timeout, &values);
+                                    if let Some(pipe) = &j.pipe {
+                                        self.pipes.send(pipe, "start", &values, &self.env);
+                                    }
                                 }
                             }
                         }
                     }
+                    let fingers = s.fingers;
+                    self.handle_workspace(fingers, "start");
+                }
+                if !dragging {
+                    xdoh.release_lock();
                 }
             }
             GestureSwipeEvent::Update(e) => {
                 let (x, y) = (e.dx(), e.dy());
+                let elapsed = e.time_usec().saturating_sub(self.last_time);
+                self.last_time = e.time_usec();
                 let swipe_dir = SwipeDir::dir(x, y);
+                if let Some(&(px, py)) = self.path.last() {
+                    self.path.push((px + x, py + y));
+                }
+                let (vx, vy) = self.velocity(e.time());
 
                 if let Gesture::Swipe(s) = &self.event {
-                    log::debug!("{:?}  {:?}", &swipe_dir, &s.fingers);
-                    for gesture in &self.config.clone().read().unwrap().gestures {
+                    log::debug!("{:?}  {:?}  {:?}", &swipe_dir, &s.fingers, (vx, vy));
+                    for (n, gesture) in self
+                        .config
+                        .clone()
+                        .read()
+                        .unwrap()
+                        .active_gestures()
+                        .enumerate()
+                    {
                         if let Gesture::Swipe(j) = gesture {
-                            if j.fingers == s.fingers {
-                                let is_xorg_condition = xdoh.is_xorg
-                                    && j.acceleration.is_some()
-                                    && j.mouse_up_delay.is_some()
-                                    && j.direction == SwipeDir::Any;
+                            if j.fingers == s.fingers && self.window_allows(n) {
+                                let is_xorg_condition = xdoh.is_xorg && j.is_drag();
                                 if is_xorg_condition {
-                                    let x_val =
-                                        x * j.acceleration.unwrap_or_default() as f64 / 10.0;
-                                    let y_val =
-                                        y * j.acceleration.unwrap_or_default() as f64 / 10.0;
-                                    xdoh.move_mouse_relative(x_val as i32, y_val as i32);
-                                } else if j.direction == swipe_dir || j.direction == SwipeDir::Any {
-                                    exec_command_from_string(
-                                        &j.update.as_ref().unwrap_or(&String::new()),
-                                        x,
-                                        y,
-                                        0.0,
-                                        0.0,
-                                    )?;
+                                    let (dx, dy) = match j.unaccelerated {
+                                        Some(true) => (e.dx_unaccelerated(), e.dy_unaccelerated()),
+                                        _ => (x, y),
+                                    };
+                                    let drag = self.drags.entry(n).or_default();
+                                    let speed = drag.speed(dx, dy, elapsed);
+                                    let (mx, my) = j.drag_delta(dx, dy, speed);
+                                    let (mx, my) = drag.pixels(mx, my);
+                                    xdoh.move_mouse_relative(mx, my);
+                                    if let Some(zone) = j.edge_scroll {
+                                        xdoh.edge_scroll(zone);
+                                    }
+                                } else if (j.direction == swipe_dir || j.direction == SwipeDir::Any)
+                                    && j.matches_velocity(vx.hypot(vy))
+                                {
+                                    let values = Values {
+                                        kind: "swipe",
+                                        delta_x: x,
+                                        delta_y: y,
+                                        velocity_x: vx,
+                                        velocity_y: vy,
+                                        fingers: s.fingers,
+                                        direction: swipe_dir.name(),
+                                        ..Default::default()
+                                    };
+                                    self.run_update(
+                                        n,
+                                        j.update.as_ref(),
+                                        j.max_rate,
+                                        j.timeout,
+                                        &values,
+                                    );
+                                    if let Some(pipe) = &j.pipe {
+                                        self.pipes.send(pipe, "update", &values, &self.env);
+                                    }
                                 }
                             }
                         }
                     }
-                    self.event = Gesture::Swipe(Swipe {
-                        direction: swipe_dir,
-                        fingers: s.fingers,
-                        update: None,
-                        start: None,
-                        end: None,
-                        acceleration: None,
-                        mouse_up_delay: None,
-                    })
+                    let fingers = s.fingers;
+                    if let Gesture::Swipe(s) = &mut self.event {
+                        s.direction = swipe_dir;
+                    }
+                    self.handle_repeat(fingers, x, y, vx, vy);
+                    self.handle_workspace(fingers, "update");
                 }
             }
             GestureSwipeEvent::End(e) => {
+                let mut workspace_phase = "cancel";
                 if let Gesture::Swipe(s) = &self.event {
+                    let (x, y) = self.path.last().copied().unwrap_or_default();
+                    // Gestures with a `commit-threshold` go by where the swipe went rather than
+                    // by its last movement, which is backwards when it was abandoned
+                    let overall = SwipeDir::dir(x, y);
+                    let (fx, fy) = self
+                        .path
+                        .iter()
+                        .copied()
+                        .max_by(|a, b| a.0.hypot(a.1).total_cmp(&b.0.hypot(b.1)))
+                        .unwrap_or_default();
+                    let furthest = SwipeDir::dir(fx, fy);
                     if !e.cancelled() {
-                        for gesture in &self.config.clone().read().unwrap().gestures {
+                        let stroke_matched = self.handle_stroke(s.fingers);
+                        let (vx, vy) = self.velocity(e.time());
+                        for (n, gesture) in self
+                            .config
+                            .clone()
+                            .read()
+                            .unwrap()
+                            .active_gestures()
+                            .enumerate()
+                        {
                             if let Gesture::Swipe(j) = gesture {
-                                if j.fingers == s.fingers {
-                                    let is_xorg_condition = xdoh.is_xorg
-                                        && j.acceleration.is_some()
-                                        && j.mouse_up_delay.is_some()
-                                        && j.direction == SwipeDir::Any;
+                                if j.fingers == s.fingers && self.window_allows(n) {
+                                    let is_xorg_condition = xdoh.is_xorg && j.is_drag();
                                     if is_xorg_condition {
+                                        if j.drag_lock == Some(true) {
+                                            xdoh.lock_drag();
+                                        } else {
+                                            xdoh.mouse_up_delay(
+                                                j.drag_button(),
+                                                j.mouse_up_delay.unwrap_or_default(),
+                                            );
+                                        }
+                                        continue;
+                                    } else if stroke_matched {
+                                        continue;
+                                    }
+                                    let values = Values {
+                                        kind: "swipe",
+                                        velocity_x: vx,
+                                        velocity_y: vy,
+                                        fingers: s.fingers,
+                                        direction: s.direction.name(),
+                                        ..Default::default()
+                                    };
+                                    let direction = match j.commit_threshold {
+                                        Some(_) => &overall,
+                                        None => &s.direction,
+                                    };
+                                    if (j.direction == *direction || j.direction == SwipeDir::Any)
+                                        && j.committed(x, y)
+                                        && j.matches_velocity(vx.hypot(vy))
+                                    {
+                                        self.run((n, "end"), j.end.as_ref(), j.timeout, &values);
+                                        if let Some(pipe) = &j.pipe {
+                                            self.pipes.send(pipe, "end", &values, &self.env);
+                                        }
+                                    } else if !j.committed(x, y)
+                                        && (j.direction == furthest || j.direction == SwipeDir::Any)
+                                    {
+                                        // Swiped back, or not far enough
+                                        self.run(
+                                            (n, "cancel"),
+                                            j.cancel.as_ref(),
+                                            j.timeout,
+                                            &values,
+                                        );
+                                        if let Some(pipe) = &j.pipe {
+                                            self.pipes.send(pipe, "cancel", &values, &self.env);
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        // A recognized stroke replaces the switch like it replaces `end`
+                        if !stroke_matched {
+                            workspace_phase = "end";
+                        }
+                    } else {
+                        // Only `cancel` and pipes hear of the swipe ending
+                        for (n, gesture) in self
+                            .config
+                            .clone()
+                            .read()
+                            .unwrap()
+                            .active_gestures()
+                            .enumerate()
+                        {
+                            if let Gesture::Swipe(j) = gesture {
+                                if xdoh.is_xorg && j.is_drag() {
+                                    // Even a locked drag lets go when it is cancelled
+                                    if j.fingers == s.fingers && self.window_allows(n) {
                                         xdoh.mouse_up_delay(
-                                            1,
-                                            j.mouse_up_delay.clone().unwrap_or_default(),
+                                            j.drag_button(),
+                                            j.mouse_up_delay.unwrap_or_default(),
                                         );
-                                    } else if j.direction == s.direction
-                                        || j.direction == SwipeDir::Any
-                                    {
-                                        exec_command_from_string(
-                                            &j.end.as_ref().unwrap_or(&String::new()),
-                                            0.0,
-                                            0.0,
-                                            0.0,
-                                            0.0,
-                                        )?;
+                                    }
+                                    continue;
+                                }
+                                let direction = match j.commit_threshold {
+                                    Some(_) => &furthest,
+                                    None => &s.direction,
+                                };
+                                if j.fingers == s.fingers
+                                    && self.window_allows(n)
+                                    && (j.direction == *direction || j.direction == SwipeDir::Any)
+                                {
+                                    let values = Values {
+                                        kind: "swipe",
+                                        fingers: s.fingers,
+                                        direction: s.direction.name(),
+                                        ..Default::default()
+                                    };
+                                    self.run((n, "cancel"), j.cancel.as_ref(), j.timeout, &values);
+                                    if let Some(pipe) = &j.pipe {
+                                        self.pipes.send(pipe, "cancel", &values, &self.env);
                                     }
                                 }
                             }
                         }
                     }
+                    let fingers = s.fingers;
+                    self.handle_workspace(fingers, workspace_phase);
                 }
             }
             _ => (),
         }
         Ok(())
     }
+
+    /// Prepare for the events of a gesture that is beginning
+    fn begin(&mut self, xdoh: &XDoHandler) {
+        self.refresh_window(xdoh);
+        self.env = self
+            .config
+            .read()
+            .unwrap()
+            .environment
+            .resolve(&self.session);
+    }
+
+    /// Look up the focused window and check it against the window filter of every gesture, so
+    /// the result can be reused for all events of the gesture that is beginning
+    fn refresh_window(&mut self, xdoh: &XDoHandler) {
+        let config = self.config.clone();
+        let config = config.read().unwrap();
+        let window = self.window_query.window(query_command(&config));
+        self.window = if !filters_window(&config) {
+            None
+        } else if config.window_query.is_some() {
+            window
+        } else {
+            xdoh.active_window()
+        };
+        log::debug!("Window: {:?}", &self.window);
+        self.match_window(&config);
+    }
+
+    /// Check the window again if the gestures moved around in a reload or profile switch since
+    /// the gesture began, so that the matches line up with them
+    fn sync_window(&mut self) {
+        let config = self.config.clone();
+        let config = config.read().unwrap();
+        if config.generation != self.generation {
+            self.match_window(&config);
+        }
+    }
+
+    /// Check the focused window against the window filter of every active gesture of `config`
+    fn match_window(&mut self, config: &Config) {
+        self.generation = config.generation;
+        self.window_matches = config
+            .active_gestures()
+            .map(|g| g.window().map_or(true, |w| w.matches(self.window.as_ref())))
+            .collect();
+    }
+
+    /// Queue a command of an active gesture, with `values` filled into its placeholders
+    fn run(&self, key: Key, action: Option<&Action>, timeout: Option<u32>, values: &Values) {
+        if let Some(action) = action.filter(|a| !a.is_empty()) {
+            let values = Values {
+                phase: key.1,
+                ..*values
+            };
+            if let Some(job) = self.job(key, action, &values) {
+                self.executor.submit(job.timeout(timeout));
+            }
+        }
+    }
+
+    /// Queue the update command of the `n`th active gesture, which replaces one that hasn't run
+    /// yet
+    fn run_update(
+        &self,
+        n: usize,
+        action: Option<&Action>,
+        max_rate: Option<u32>,
+        timeout: Option<u32>,
+        values: &Values,
+    ) {
+        if let Some(action) = action.filter(|a| !a.is_empty()) {
+            let values = Values {
+                phase: "update",
+                ..*values
+            };
+            if let Some(job) = self.job((n, "update"), action, &values) {
+                self.executor
+                    .submit(job.coalesced(max_rate).timeout(timeout));
+            }
+        }
+    }
+
+    /// The job running `action`, with `values` also passed in the environment of a process
+    fn job(&self, key: Key, action: &Action, values: &Values) -> Option<Job> {
+        let rendered = match action {
+            Action::DBus(call) => call.render(values, &self.env).map(|c| Job::call(key, c)),
+            Action::Compositor(command) => command
+                .render(values, &self.env)
+                .map(|m| Job::message(key, m)),
+            _ => {
+                let mut command = action.command(values)?;
+                command.envs(values.env());
+                self.env.apply(&mut command);
+                Ok(Job::new(key, command))
+            }
+        };
+        match rendered {
+            Ok(job) => Some(job.generation(self.generation)),
+            Err(e) => {
+                log::error!("Could not run {action}: {e}");
+                None
+            }
+        }
+    }
+
+    fn window_allows(&self, index: usize) -> bool {
+        self.window_matches.get(index).copied().unwrap_or(true)
+    }
+
+    /// Fire the `repeat-every` actions of swipes whose accumulated travel crossed another step
+    fn handle_repeat(&mut self, fingers: i32, dx: f64, dy: f64, vx: f64, vy: f64) {
+        let (Some(&(x, y)), Some(&first)) = (self.path.last(), self.path.get(1)) else {
+            return;
+        };
+        let config = self.config.clone();
+        for (i, gesture) in config.read().unwrap().active_gestures().enumerate() {
+            let Gesture::Swipe(j) = gesture else {
+                continue;
+            };
+            let Some(every) = j.repeat_every.filter(|&e| e > 0) else {
+                continue;
+            };
+            if j.fingers != fingers || !self.window_allows(i) || !j.matches_velocity(vx.hypot(vy)) {
+                continue;
+            }
+
+            let direction = j.repeat_direction(first);
+            let (ux, uy) = direction.unit_vector();
+            let fired = self.repeat_steps.entry(i).or_insert(0);
+            let (forward, backward) = steps(*fired, x * ux + y * uy, every);
+            *fired += forward as i64 - backward as i64;
+            let (name, action, count) = if forward > 0 {
+                ("repeat-forward", &j.repeat_forward, forward)
+            } else {
+                ("repeat-backward", &j.repeat_backward, backward)
+            };
+            if count > MAX_REPEAT_STEPS {
+                log::debug!("Skipping {} {name} steps", count - MAX_REPEAT_STEPS);
+            }
+            for _ in 0..count.min(MAX_REPEAT_STEPS) {
+                self.run(
+                    (i, name),
+                    action.as_ref(),
+                    j.timeout,
+                    &Values {
+                        kind: "swipe",
+                        delta_x: dx,
+                        delta_y: dy,
+                        velocity_x: vx,
+                        velocity_y: vy,
+                        fingers,
+                        direction: direction.name(),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+    }
+
+    /// Follow the current swipe with the `workspace` swipes: stream its progress to
+    /// `workspace-progress`, or switch the workspace once it ends far enough from where it began
+    fn handle_workspace(&mut self, fingers: i32, phase: &'static str) {
+        let (x, y) = self.path.last().copied().unwrap_or_default();
+        let config = self.config.clone();
+        for (n, gesture) in config.read().unwrap().active_gestures().enumerate() {
+            let Gesture::Swipe(j) = gesture else {
+                continue;
+            };
+            let Some(axis) = j.workspace else {
+                continue;
+            };
+            if j.fingers != fingers || !self.window_allows(n) {
+                continue;
+            }
+            let distance = j.workspace_distance.unwrap_or(DEFAULT_WORKSPACE_DISTANCE);
+            let progress = axis.progress(x, y, distance);
+            // Where the swipe settles: 1 on the next workspace, -1 on the previous one
+            let step = match phase {
+                "end" if progress.abs() >= WORKSPACE_COMMIT => progress.signum() as i32,
+                _ => 0,
+            };
+            match &j.workspace_progress {
+                Some(pipe) => {
+                    let line = match phase {
+                        "start" => "start".to_string(),
+                        "update" => format!("update {progress:.3}"),
+                        _ => format!("end {step}"),
+                    };
+                    self.pipes.send_line(pipe, line, &self.env);
+                }
+                None if step != 0 => self.switch_workspace(n, step > 0, j.timeout),
+                None => {}
+            }
+        }
+    }
+
+    /// Tell the compositor to switch to the next or previous workspace
+    fn switch_workspace(&self, n: usize, next: bool, timeout: Option<u32>) {
+        let Some(compositor) = Compositor::detect(&self.env) else {
+            log::warn!("Switching workspaces needs sway or Hyprland, or a `workspace-progress`");
+            return;
+        };
+        match Message::new(compositor, &self.env, compositor.workspace_command(next)) {
+            Ok(message) => {
+                let job = Job::message((n, "workspace"), message).timeout(timeout);
+                self.executor.submit(job);
+            }
+            Err(e) => log::error!("Could not switch workspaces: {e}"),
+        }
+    }
+
+    /// Average velocity (units per second) of the current swipe at `time` (ms)
+    fn velocity(&self, time: u32) -> (f64, f64) {
+        let elapsed = time.wrapping_sub(self.start_time) as f64 / 1000.0;
+        match self.path.last() {
+            Some(&(x, y)) if elapsed > 0.0 => (x / elapsed, y / elapsed),
+            _ => (0.0, 0.0),
+        }
+    }
+
+    /// Match the path of the swipe that just ended against the configured strokes.
+    /// Returns whether a stroke was recognized, in which case the swipe's own end action is
+    /// skipped.
+    fn handle_stroke(&self, fingers: i32) -> bool {
+        let config = self.config.read().unwrap();
+        let strokes: Vec<(usize, &Stroke)> = config
+            .active_gestures()
+            .enumerate()
+            .filter_map(|(n, g)| match g {
+                Gesture::Stroke(s) if s.fingers == fingers && self.window_allows(n) => Some((n, s)),
+                _ => None,
+            })
+            .collect();
+        if strokes.is_empty() {
+            return false;
+        }
+
+        let templates: Vec<Template> = config
+            .stroke_templates()
+            .into_iter()
+            .filter(|t| strokes.iter().any(|(_, s)| s.shape == t.name))
+            .collect();
+        let Some((shape, score)) = recognize(&self.path, &templates) else {
+            return false;
+        };
+        log::debug!(
+            "Stroke: shape={:?} score={:?} fingers={:?}",
+            shape,
+            score,
+            fingers
+        );
+
+        let mut matched = false;
+        for (n, s) in strokes.iter().filter(|(_, s)| s.shape == shape) {
+            if score >= s.min_score.unwrap_or(DEFAULT_MIN_SCORE) {
+                matched = true;
+                self.run(
+                    (*n, "action"),
+                    s.action.as_ref(),
+                    s.timeout,
+                    &Values {
+                        kind: "stroke",
+                        fingers,
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+        matched
+    }
+}
+
+/// Whether any active gesture of `config` is restricted to some windows
+fn filters_window(config: &Config) -> bool {
+    config
+        .active_gestures()
+        .any(|g| g.window().is_some_and(|w| !w.is_empty()))
+}
+
+/// The `window-query` of `config` if it is needed
+fn query_command(config: &Config) -> Option<&str> {
+    config
+        .window_query
+        .as_deref()
+        .filter(|_| filters_window(config))
+}
+
+/// Motion of the pointer during a drag, carried from one update to the next
+#[derive(Debug, Default)]
+pub struct DragMotion {
+    /// Movement short of a whole pixel
+    remainder: (f64, f64),
+    /// Speed (units per second) of the last update
+    speed: f64,
+}
+
+impl DragMotion {
+    /// Speed of a movement of `dx` and `dy` made `elapsed` µs after the last update. Updates
+    /// with the same timestamp keep the last speed.
+    pub fn speed(&mut self, dx: f64, dy: f64, elapsed: u64) -> f64 {
+        if elapsed > 0 {
+            self.speed = dx.hypot(dy) * 1_000_000.0 / elapsed as f64;
+        }
+        self.speed
+    }
+
+    /// Whole pixels to move the pointer by for a movement of `x` and `y`, keeping the fraction
+    /// of a pixel left over so that slow movements add up
+    pub fn pixels(&mut self, x: f64, y: f64) -> (i32, i32) {
+        let (x, y) = (x + self.remainder.0, y + self.remainder.1);
+        self.remainder = (x.fract(), y.fract());
+        (x.trunc() as i32, y.trunc() as i32)
+    }
 }
 
 pub struct Interface;
@@ -0,0 +1,631 @@
+//! Runs the commands of gestures on a fixed number of workers, each of which is held until its
+//! command exits, so that a burst of events can't start an unbounded number of processes
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::{Read, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ChildStderr, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use knuffel::{span::Span, Decode};
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::libc;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+
+use crate::compositor::Message;
+use crate::config::Origin;
+use crate::dbus::Call;
+use crate::kdl::NodeWriter;
+
+pub const DEFAULT_WORKERS: u32 = 4;
+pub const DEFAULT_QUEUE_SIZE: u32 = 64;
+/// How often a command is checked for having exited when the kernel can't tell when it does
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Most output of a command to stderr that is kept for the log
+const STDERR_LIMIT: usize = 4096;
+/// How long a call waits for its reply without a timeout, short since it holds up a worker
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// `executor workers=4 queue-size=64 timeout=5000`, applied again when the config is reloaded
+#[derive(Decode, PartialEq, Debug, Clone, Default)]
+#[knuffel(span_type = Span)]
+pub struct ExecutorSettings {
+    /// Number of commands that can be started at the same time
+    #[knuffel(property)]
+    pub workers: Option<u32>,
+    /// Number of commands that can wait for a worker before new ones are dropped
+    #[knuffel(property)]
+    pub queue_size: Option<u32>,
+    /// Milliseconds after which `update` commands of gestures without a `timeout` of their own
+    /// are killed
+    #[knuffel(property)]
+    pub timeout: Option<u32>,
+    #[knuffel(span)]
+    pub origin: Origin,
+}
+
+impl ExecutorSettings {
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_none() && self.queue_size.is_none() && self.timeout.is_none()
+    }
+
+    /// The settings of `other` where it has any, these otherwise
+    pub fn merge(&mut self, other: ExecutorSettings) {
+        if !other.is_empty() {
+            self.origin = other.origin;
+        }
+        self.workers = other.workers.or(self.workers);
+        self.queue_size = other.queue_size.or(self.queue_size);
+        self.timeout = other.timeout.or(self.timeout);
+    }
+}
+
+impl fmt::Display for ExecutorSettings {
+    /// Write the settings as a KDL node
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        NodeWriter::new(f, "executor")
+            .property("workers", self.workers.as_ref())
+            .property("queue-size", self.queue_size.as_ref())
+            .property("timeout", self.timeout.as_ref())
+            .finish()
+    }
+}
+
+/// The command of a binding: the index of the gesture among the active ones and the name of the
+/// command
+pub type Key = (usize, &'static str);
+
+/// A key along with the generation of the config its gesture index refers to
+type Slot = (u64, Key);
+
+/// A command waiting for a worker
+#[derive(Debug)]
+pub struct Job {
+    key: Slot,
+    task: Task,
+    coalesce: bool,
+    min_interval: Option<Duration>,
+    timeout: Option<Duration>,
+}
+
+/// What a job does
+#[derive(Debug)]
+enum Task {
+    Spawn(Command),
+    Call(Call),
+    Message(Message),
+}
+
+/// Why a call or a message did not succeed
+#[derive(Debug)]
+pub enum CallError {
+    /// The bus or socket could not be reached, or the request not be sent
+    Failed(String),
+    /// The other side replied with an error
+    Reply(String),
+    TimedOut,
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Failed(e) => write!(f, "failed: {e}"),
+            CallError::Reply(e) => write!(f, "returned an error: {e}"),
+            CallError::TimedOut => f.write_str("timed out"),
+        }
+    }
+}
+
+impl Job {
+    pub fn new(key: Key, command: Command) -> Self {
+        Self::with_task(key, Task::Spawn(command))
+    }
+
+    /// Job that calls a method over D-Bus and waits for its reply
+    pub fn call(key: Key, call: Call) -> Self {
+        Self::with_task(key, Task::Call(call))
+    }
+
+    /// Job that sends a command to the compositor and waits for its reply
+    pub fn message(key: Key, message: Message) -> Self {
+        Self::with_task(key, Task::Message(message))
+    }
+
+    fn with_task(key: Key, task: Task) -> Self {
+        Self {
+            key: (0, key),
+            task,
+            coalesce: false,
+            min_interval: None,
+            timeout: None,
+        }
+    }
+
+    /// Tie the key to the config of `generation`, so that a binding of an older config that took
+    /// the same place in the active gestures doesn't hold up or rate limit it
+    pub fn generation(mut self, generation: u64) -> Self {
+        self.key.0 = generation;
+        self
+    }
+
+    /// Kill the command along with its children after `timeout` milliseconds, or stop waiting
+    /// for the reply of a call, instead of after the executor's default
+    pub fn timeout(mut self, timeout: Option<u32>) -> Self {
+        self.timeout = timeout.map(|t| Duration::from_millis(t.into()));
+        self
+    }
+
+    /// Run the command only once the previous one of the binding has exited, replacing one that
+    /// is still waiting, and at most `max_rate` times per second
+    pub fn coalesced(mut self, max_rate: Option<u32>) -> Self {
+        self.coalesce = true;
+        self.min_interval = max_rate
+            .filter(|&r| r > 0)
+            .map(|r| Duration::from_secs_f64(1.0 / r as f64));
+        self
+    }
+}
+
+/// Counters reported by `gestures metrics`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    /// Commands waiting for a worker
+    pub queued: usize,
+    /// Most commands that were waiting at the same time
+    pub max_queued: usize,
+    /// Commands that were started and haven't exited yet, and calls waiting for their reply
+    pub running: usize,
+    pub submitted: u64,
+    /// Commands replaced by a newer one of the same binding before they ran
+    pub coalesced: u64,
+    /// Commands dropped because the queue was full
+    pub dropped: u64,
+    pub started: u64,
+    /// Commands that could not be started, and calls that could not be sent
+    pub failed: u64,
+    /// Commands that exited with a status other than 0 or were killed, and calls that returned
+    /// an error
+    pub errors: u64,
+    /// Commands killed because they ran out of time, and calls that got no reply in time
+    pub timed_out: u64,
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "queued {}", self.queued)?;
+        writeln!(f, "max-queued {}", self.max_queued)?;
+        writeln!(f, "running {}", self.running)?;
+        writeln!(f, "submitted {}", self.submitted)?;
+        writeln!(f, "coalesced {}", self.coalesced)?;
+        writeln!(f, "dropped {}", self.dropped)?;
+        writeln!(f, "started {}", self.started)?;
+        writeln!(f, "failed {}", self.failed)?;
+        writeln!(f, "errors {}", self.errors)?;
+        writeln!(f, "timed-out {}", self.timed_out)
+    }
+}
+
+/// Handle to the worker pool, cheap to clone
+#[derive(Debug, Clone)]
+pub struct Executor {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    /// Signaled when a job may have become runnable, or a worker may have to stop
+    jobs: Condvar,
+    /// Commands that were started, handed to the thread watching them
+    started: Mutex<Sender<Running>>,
+    /// Written to when a command was started, so that the watching thread looks at it
+    wake: UnixStream,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    queue: VecDeque<Job>,
+    /// Bindings whose coalesced command is running
+    running: HashSet<Slot>,
+    /// When each rate limited binding last started a command
+    last_start: HashMap<Slot, Instant>,
+    /// Number of commands that were started and haven't exited yet
+    children: usize,
+    /// Number of D-Bus calls and compositor commands waiting for their reply
+    calls: usize,
+    metrics: Metrics,
+    /// Number of workers there should be, and that there are
+    workers: usize,
+    alive: usize,
+    queue_size: usize,
+    default_timeout: Option<Duration>,
+}
+
+/// A command that was started
+#[derive(Debug)]
+struct Running {
+    key: Slot,
+    coalesce: bool,
+    description: String,
+    child: Child,
+    stderr: Option<ChildStderr>,
+    output: Vec<u8>,
+    deadline: Option<Instant>,
+}
+
+impl Running {
+    /// Keep what the command wrote to stderr so far, without blocking, and pass on what doesn't
+    /// fit
+    fn read_stderr(&mut self) {
+        let Some(stderr) = &mut self.stderr else {
+            return;
+        };
+        let mut buf = [0; 1024];
+        loop {
+            match stderr.read(&mut buf) {
+                Ok(0) => {
+                    self.stderr = None;
+                    break;
+                }
+                Ok(n) if self.output.len() < STDERR_LIMIT => self.output.extend(&buf[..n]),
+                Ok(n) => {
+                    let _ = std::io::Write::write_all(&mut std::io::stderr(), &buf[..n]);
+                }
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+impl State {
+    /// Remove the first job that may run now, or tell when the next rate limited one may
+    fn take_runnable(&mut self, now: Instant) -> Result<Job, Option<Instant>> {
+        // Every worker is held by a command that hasn't exited or a call waiting for its reply
+        if self.children + self.calls >= self.workers {
+            return Err(None);
+        }
+        let mut wake: Option<Instant> = None;
+        for (i, job) in self.queue.iter().enumerate() {
+            if job.coalesce && self.running.contains(&job.key) {
+                continue;
+            }
+            if let (Some(interval), Some(last)) = (job.min_interval, self.last_start.get(&job.key))
+            {
+                let ready = *last + interval;
+                if ready > now {
+                    wake = Some(wake.map_or(ready, |w| w.min(ready)));
+                    continue;
+                }
+            }
+            return Ok(self.queue.remove(i).unwrap());
+        }
+        Err(wake)
+    }
+}
+
+impl Executor {
+    pub fn new(settings: &ExecutorSettings) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let (wake, woken) = UnixStream::pair().expect("Could not create a socket pair");
+        woken
+            .set_nonblocking(true)
+            .expect("Could not make a socket non-blocking");
+        let executor = Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State::default()),
+                jobs: Condvar::new(),
+                started: Mutex::new(sender),
+                wake,
+            }),
+        };
+        let shared = executor.shared.clone();
+        thread::spawn(move || reap(&shared, receiver, woken));
+        executor.apply(settings);
+        executor
+    }
+
+    /// Use `settings` from now on, starting workers or letting some go once they are idle.
+    /// Commands that are running keep their timeout.
+    pub fn apply(&self, settings: &ExecutorSettings) {
+        let mut state = self.state();
+        state.workers = settings.workers.unwrap_or(DEFAULT_WORKERS).max(1) as usize;
+        state.queue_size = settings.queue_size.unwrap_or(DEFAULT_QUEUE_SIZE) as usize;
+        state.default_timeout = settings.timeout.map(|t| Duration::from_millis(t.into()));
+        while state.alive < state.workers {
+            state.alive += 1;
+            let shared = self.shared.clone();
+            thread::spawn(move || work(&shared));
+        }
+        self.shared.jobs.notify_all();
+    }
+
+    /// Queue `job`, or drop it if too many commands are waiting already
+    pub fn submit(&self, job: Job) {
+        let mut state = self.state();
+        state.metrics.submitted += 1;
+        // Bindings of older configs won't start anything again
+        let generation = job.key.0;
+        state.last_start.retain(|(g, _), _| *g >= generation);
+        if job.coalesce {
+            if let Some(pending) = state
+                .queue
+                .iter_mut()
+                .find(|p| p.coalesce && p.key == job.key)
+            {
+                *pending = job;
+                state.metrics.coalesced += 1;
+                return;
+            }
+        }
+        if state.queue.len() >= state.queue_size {
+            log::warn!(
+                "Too many commands are waiting to run, dropping {:?}",
+                job.task
+            );
+            state.metrics.dropped += 1;
+            return;
+        }
+        state.queue.push_back(job);
+        state.metrics.max_queued = state.metrics.max_queued.max(state.queue.len());
+        self.shared.jobs.notify_one();
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        let state = self.state();
+        Metrics {
+            queued: state.queue.len(),
+            running: state.children + state.calls,
+            ..state.metrics.clone()
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.shared.state.lock().unwrap()
+    }
+}
+
+fn work(shared: &Arc<Shared>) {
+    let mut state = shared.state.lock().unwrap();
+    loop {
+        if state.alive > state.workers {
+            state.alive -= 1;
+            return;
+        }
+        let now = Instant::now();
+        let job = match state.take_runnable(now) {
+            Ok(job) => job,
+            Err(Some(wake)) => {
+                state = shared.jobs.wait_timeout(state, wake - now).unwrap().0;
+                continue;
+            }
+            Err(None) => {
+                state = shared.jobs.wait(state).unwrap();
+                continue;
+            }
+        };
+        if job.coalesce {
+            state.running.insert(job.key);
+        }
+        if job.min_interval.is_some() {
+            state.last_start.insert(job.key, now);
+        }
+        // The default only applies to commands that are waited on, not to programs a gesture
+        // opens, like a browser
+        let timeout = job
+            .timeout
+            .or(state.default_timeout.filter(|_| job.coalesce));
+        let (description, result) = match job.task {
+            Task::Spawn(command) => {
+                // Taken before the command starts, so that other workers see the worker is held
+                state.children += 1;
+                drop(state);
+                spawn(shared, job.key, job.coalesce, timeout, command);
+                state = shared.state.lock().unwrap();
+                continue;
+            }
+            Task::Call(call) => {
+                state.calls += 1;
+                drop(state);
+                log::debug!("{call}");
+                (
+                    call.to_string(),
+                    call.send(timeout.unwrap_or(REPLY_TIMEOUT)),
+                )
+            }
+            Task::Message(message) => {
+                state.calls += 1;
+                drop(state);
+                log::debug!("{message}");
+                (
+                    message.to_string(),
+                    message.send(timeout.unwrap_or(REPLY_TIMEOUT)),
+                )
+            }
+        };
+        state = shared.state.lock().unwrap();
+        state.calls -= 1;
+        if let Err(e) = &result {
+            log::warn!("{description} {e}");
+        }
+        match result {
+            Err(CallError::Failed(_)) => state.metrics.failed += 1,
+            Err(CallError::Reply(_)) => state.metrics.errors += 1,
+            Err(CallError::TimedOut) => state.metrics.timed_out += 1,
+            Ok(()) => {}
+        }
+        if !matches!(result, Err(CallError::Failed(_))) {
+            state.metrics.started += 1;
+        }
+        state.running.remove(&job.key);
+        shared.jobs.notify_all();
+    }
+}
+
+/// Start `command` and hand it to the thread watching the commands until they exit
+fn spawn(
+    shared: &Shared,
+    key: Slot,
+    coalesce: bool,
+    timeout: Option<Duration>,
+    mut command: Command,
+) {
+    let description = format!("{command:?}");
+    log::debug!("{description}");
+    // Its own process group lets a timeout kill whatever the command started as well
+    let spawned = command.process_group(0).stderr(Stdio::piped()).spawn();
+
+    let mut state = shared.state.lock().unwrap();
+    match spawned {
+        Ok(mut child) => {
+            let stderr = child.stderr.take();
+            if let Some(stderr) = &stderr {
+                let _ = fcntl(stderr.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK));
+            }
+            state.metrics.started += 1;
+            let running = Running {
+                key,
+                coalesce,
+                description,
+                child,
+                stderr,
+                output: Vec::new(),
+                deadline: timeout.map(|t| Instant::now() + t),
+            };
+            shared.started.lock().unwrap().send(running).unwrap();
+            let _ = (&shared.wake).write(&[0]);
+        }
+        Err(e) => {
+            log::error!("Failed to run {description}: {e}");
+            state.metrics.failed += 1;
+            state.children -= 1;
+            state.running.remove(&key);
+            shared.jobs.notify_all();
+        }
+    }
+}
+
+/// Watch every command that was started until it exits, collecting what it writes to stderr and
+/// killing it if it runs out of time
+fn reap(shared: &Shared, started: Receiver<Running>, mut woken: UnixStream) {
+    let mut watched: Vec<(Running, Option<OwnedFd>)> = Vec::new();
+    loop {
+        for running in started.try_iter() {
+            let pidfd = pidfd_open(running.child.id());
+            watched.push((running, pidfd));
+        }
+        // Commands without a pidfd are checked every so often instead
+        let mut wait = watched
+            .iter()
+            .any(|(_, pidfd)| pidfd.is_none())
+            .then_some(POLL_INTERVAL);
+        let now = Instant::now();
+        for deadline in watched.iter().filter_map(|(running, _)| running.deadline) {
+            let left = deadline.saturating_duration_since(now);
+            wait = Some(wait.map_or(left, |w| w.min(left)));
+        }
+        {
+            let fds: Vec<BorrowedFd> = watched
+                .iter()
+                .flat_map(|(running, pidfd)| {
+                    let stderr = running.stderr.as_ref().map(|e| e.as_fd());
+                    pidfd
+                        .as_ref()
+                        .map(|fd| fd.as_fd())
+                        .into_iter()
+                        .chain(stderr)
+                })
+                .chain([woken.as_fd()])
+                .collect();
+            let mut fds: Vec<PollFd> = fds
+                .iter()
+                .map(|fd| PollFd::new(fd, PollFlags::POLLIN))
+                .collect();
+            let wait = wait.map_or(-1, |w| w.as_millis().min(i32::MAX as u128) as i32);
+            let _ = poll(&mut fds, wait);
+        }
+        while woken.read(&mut [0; 64]).is_ok_and(|n| n > 0) {}
+
+        let mut i = 0;
+        while i < watched.len() {
+            match check(shared, &mut watched[i].0) {
+                Some(status) => finish(shared, watched.swap_remove(i).0, status),
+                None => i += 1,
+            }
+        }
+    }
+}
+
+/// Whether the command has exited, along with its status if it could be waited for. Kills it if
+/// it ran out of time.
+fn check(shared: &Shared, running: &mut Running) -> Option<Option<ExitStatus>> {
+    running.read_stderr();
+    match running.child.try_wait() {
+        Ok(Some(status)) => return Some(Some(status)),
+        Ok(None) => {}
+        Err(e) => {
+            log::error!("Could not wait for {}: {e}", running.description);
+            let _ = running.child.kill();
+            return Some(None);
+        }
+    }
+    // Not reaped yet, so the process group can't have been reused
+    if running.deadline.is_some_and(|d| d <= Instant::now()) {
+        log::warn!("{} timed out, killing it", running.description);
+        let pid = Pid::from_raw(running.child.id() as i32);
+        let _ = killpg(pid, Signal::SIGKILL);
+        running.deadline = None;
+        shared.state.lock().unwrap().metrics.timed_out += 1;
+    }
+    None
+}
+
+/// Log a command that exited if it failed, and let go of its worker
+fn finish(shared: &Shared, mut running: Running, status: Option<ExitStatus>) {
+    running.read_stderr();
+    let mut state = shared.state.lock().unwrap();
+    state.children -= 1;
+    match status {
+        Some(status) if status.success() => {}
+        Some(status) => {
+            state.metrics.errors += 1;
+            log::warn!("{} exited with {status}", running.description);
+        }
+        None => state.metrics.errors += 1,
+    }
+    let output = String::from_utf8_lossy(&running.output);
+    let output = output.trim_end();
+    if !output.is_empty() {
+        log::warn!("{} wrote to stderr: {output}", running.description);
+    }
+    if let Some(stderr) = running.stderr.take() {
+        forward(stderr);
+    }
+    if running.coalesce {
+        state.running.remove(&running.key);
+    }
+    // A job may be waiting for the worker, or for the binding's command to exit
+    shared.jobs.notify_all();
+}
+
+/// File descriptor that becomes readable when the process `pid` exits, on Linux 5.3 and later
+fn pidfd_open(pid: u32) -> Option<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    (fd >= 0).then(|| unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+/// Pass on what programs left running by a command write to stderr, as they did before it was
+/// captured. Closing the pipe instead would kill them once they write to it.
+fn forward(mut stderr: ChildStderr) {
+    let _ = fcntl(stderr.as_raw_fd(), FcntlArg::F_SETFL(OFlag::empty()));
+    thread::spawn(move || {
+        let _ = std::io::copy(&mut stderr, &mut std::io::stderr());
+    });
+}
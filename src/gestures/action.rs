@@ -0,0 +1,472 @@
+use std::{fmt, process::Command, str::FromStr};
+
+use knuffel::{
+    ast::{Literal, TypeName},
+    decode::Context,
+    errors::DecodeError,
+    span::{Span, Spanned},
+    traits::ErrorSpan,
+    Decode, DecodeScalar,
+};
+
+use crate::compositor::{Compositor, CompositorCommand};
+use crate::config::Expanded;
+use crate::dbus::DBusCall;
+use crate::kdl::ToKdl;
+
+/// Command run by a gesture, with the config's variables already expanded.
+///
+/// Given as a property like `end="xdotool key alt+Right"` it runs with `sh -c`, while a child
+/// node like `end "xdotool" "key" "alt+Right"` runs the program directly, filling in the
+/// placeholders of each argument on its own. A child node with a `method` calls that method over
+/// D-Bus instead, and one with `sway` or `hyprland` sends a command to the compositor.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Shell(Format),
+    Argv(Vec<Format>),
+    DBus(Box<DBusCall>),
+    Compositor(Box<CompositorCommand>),
+}
+
+/// Text with placeholders, written as `$name` or `{name}`, where the latter can set the number of
+/// decimals like `{delta_x:.0}`. Anything else, such as shell variables, is left alone, and `\$`
+/// and `\{` stand for a literal `$` and `{`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Format {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Value(Placeholder, Option<usize>),
+}
+
+/// Value filled into a command when it runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placeholder {
+    DeltaX,
+    DeltaY,
+    Scale,
+    DeltaAngle,
+    VelocityX,
+    VelocityY,
+    Fingers,
+    Direction,
+}
+
+impl Placeholder {
+    const ALL: [Placeholder; 8] = [
+        Placeholder::DeltaX,
+        Placeholder::DeltaY,
+        Placeholder::Scale,
+        Placeholder::DeltaAngle,
+        Placeholder::VelocityX,
+        Placeholder::VelocityY,
+        Placeholder::Fingers,
+        Placeholder::Direction,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Placeholder::DeltaX => "delta_x",
+            Placeholder::DeltaY => "delta_y",
+            Placeholder::Scale => "scale",
+            Placeholder::DeltaAngle => "delta_angle",
+            Placeholder::VelocityX => "velocity_x",
+            Placeholder::VelocityY => "velocity_y",
+            Placeholder::Fingers => "fingers",
+            Placeholder::Direction => "direction",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+/// Values of the placeholders for one run of a command
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Values {
+    /// Kind of gesture, like "swipe"
+    pub kind: &'static str,
+    /// Name of the command that runs, like "update"
+    pub phase: &'static str,
+    pub delta_x: f64,
+    pub delta_y: f64,
+    pub scale: f64,
+    pub delta_angle: f64,
+    pub velocity_x: f64,
+    pub velocity_y: f64,
+    pub fingers: i32,
+    pub direction: &'static str,
+}
+
+impl Values {
+    /// The values as `GESTURE_*` environment variables
+    pub fn env(&self) -> [(&'static str, String); 10] {
+        [
+            ("GESTURE_TYPE", self.kind.to_string()),
+            ("GESTURE_PHASE", self.phase.to_string()),
+            ("GESTURE_FINGERS", self.fingers.to_string()),
+            ("GESTURE_DIRECTION", self.direction.to_string()),
+            ("GESTURE_DELTA_X", self.delta_x.to_string()),
+            ("GESTURE_DELTA_Y", self.delta_y.to_string()),
+            ("GESTURE_SCALE", self.scale.to_string()),
+            ("GESTURE_DELTA_ANGLE", self.delta_angle.to_string()),
+            ("GESTURE_VELOCITY_X", self.velocity_x.to_string()),
+            ("GESTURE_VELOCITY_Y", self.velocity_y.to_string()),
+        ]
+    }
+}
+
+impl Action {
+    /// The process to spawn with the placeholders replaced by `values`, or `None` for a D-Bus
+    /// call or a compositor command
+    pub fn command(&self, values: &Values) -> Option<Command> {
+        match self {
+            Action::Shell(template) => {
+                let mut command = Command::new("sh");
+                command.arg("-c").arg(template.render(values));
+                Some(command)
+            }
+            Action::Argv(args) => {
+                let mut args = args.iter().map(|a| a.render(values));
+                let mut command = Command::new(args.next().unwrap_or_default());
+                command.args(args);
+                Some(command)
+            }
+            Action::DBus(_) | Action::Compositor(_) => None,
+        }
+    }
+
+    /// Whether there is nothing to run
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Action::Shell(template) => template.source.trim().is_empty(),
+            Action::Argv(args) => args.is_empty(),
+            Action::DBus(_) => false,
+            Action::Compositor(command) => command.command.source.trim().is_empty(),
+        }
+    }
+
+    /// Placeholders the command uses
+    pub fn placeholders(&self) -> impl Iterator<Item = Placeholder> + '_ {
+        let templates = match self {
+            Action::Shell(template) => std::slice::from_ref(template),
+            Action::Argv(args) => args.as_slice(),
+            Action::DBus(call) => call.args.as_slice(),
+            Action::Compositor(command) => std::slice::from_ref(&command.command),
+        };
+        templates.iter().flat_map(Format::placeholders)
+    }
+}
+
+impl Format {
+    /// The text as written in the config
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The text with the placeholders replaced by `values`
+    pub fn render(&self, values: &Values) -> String {
+        let mut command = String::with_capacity(self.source.len());
+        for part in &self.parts {
+            let number = match part {
+                Part::Text(text) => {
+                    command.push_str(text);
+                    continue;
+                }
+                Part::Value(Placeholder::Fingers, _) => {
+                    command.push_str(&values.fingers.to_string());
+                    continue;
+                }
+                Part::Value(Placeholder::Direction, _) => {
+                    command.push_str(values.direction);
+                    continue;
+                }
+                Part::Value(Placeholder::DeltaX, p) => (values.delta_x, p),
+                Part::Value(Placeholder::DeltaY, p) => (values.delta_y, p),
+                Part::Value(Placeholder::Scale, p) => (values.scale, p),
+                Part::Value(Placeholder::DeltaAngle, p) => (values.delta_angle, p),
+                Part::Value(Placeholder::VelocityX, p) => (values.velocity_x, p),
+                Part::Value(Placeholder::VelocityY, p) => (values.velocity_y, p),
+            };
+            match number {
+                (v, Some(precision)) => command.push_str(&format!("{v:.precision$}")),
+                (v, None) => command.push_str(&v.to_string()),
+            }
+        }
+        command
+    }
+
+    /// Placeholders the command uses
+    pub fn placeholders(&self) -> impl Iterator<Item = Placeholder> + '_ {
+        self.parts.iter().filter_map(|p| match p {
+            Part::Value(placeholder, _) => Some(*placeholder),
+            Part::Text(_) => None,
+        })
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut push_value = |text: &mut String, placeholder, precision| {
+            if !text.is_empty() {
+                parts.push(Part::Text(std::mem::take(text)));
+            }
+            parts.push(Part::Value(placeholder, precision));
+        };
+
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+            match c {
+                '\\' if rest.starts_with(['$', '{']) => {
+                    text.push_str(&rest[..1]);
+                    rest = &rest[1..];
+                }
+                '$' => {
+                    let len = rest
+                        .find(|c: char| !(c.is_ascii_lowercase() || c == '_'))
+                        .unwrap_or(rest.len());
+                    match Placeholder::from_name(&rest[..len]) {
+                        Some(placeholder) => {
+                            push_value(&mut text, placeholder, None);
+                            rest = &rest[len..];
+                        }
+                        None => text.push('$'),
+                    }
+                }
+                '{' => {
+                    let inner = rest.find('}').map(|end| &rest[..end]);
+                    let (name, format) = match inner.map(|i| i.split_once(':')) {
+                        Some(Some((name, format))) => (name, Some(format)),
+                        _ => (inner.unwrap_or_default(), None),
+                    };
+                    let Some(placeholder) = Placeholder::from_name(name) else {
+                        text.push('{');
+                        continue;
+                    };
+                    let precision = match format {
+                        None => None,
+                        Some(f) => match f.strip_prefix('.').map(str::parse) {
+                            Some(Ok(precision)) => Some(precision),
+                            _ => {
+                                return Err(format!(
+                                "invalid format `{f}` for `{name}`, expected something like `.2`"
+                            ))
+                            }
+                        },
+                    };
+                    push_value(&mut text, placeholder, precision);
+                    rest = &rest[inner.unwrap_or_default().len() + 1..];
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self {
+            source: s.to_string(),
+            parts,
+        })
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Action::Shell)
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Shell(template) => f.write_str(&template.source),
+            Action::Argv(args) => {
+                let args: Vec<String> = args.iter().map(ToKdl::to_kdl).collect();
+                f.write_str(&args.join(" "))
+            }
+            Action::DBus(call) => call.fmt(f),
+            Action::Compositor(command) => command.fmt(f),
+        }
+    }
+}
+
+impl<S: ErrorSpan> DecodeScalar<S> for Format {
+    fn type_check(type_name: &Option<Spanned<TypeName, S>>, ctx: &mut Context<S>) {
+        <String as DecodeScalar<S>>::type_check(type_name, ctx)
+    }
+
+    fn raw_decode(
+        value: &Spanned<Literal, S>,
+        ctx: &mut Context<S>,
+    ) -> Result<Self, DecodeError<S>> {
+        let Expanded(s) = <Expanded as DecodeScalar<S>>::raw_decode(value, ctx)?;
+        s.parse().map_err(|e| DecodeError::conversion(value, e))
+    }
+}
+
+impl<S: ErrorSpan> DecodeScalar<S> for Action {
+    fn type_check(type_name: &Option<Spanned<TypeName, S>>, ctx: &mut Context<S>) {
+        <Format as DecodeScalar<S>>::type_check(type_name, ctx)
+    }
+
+    fn raw_decode(
+        value: &Spanned<Literal, S>,
+        ctx: &mut Context<S>,
+    ) -> Result<Self, DecodeError<S>> {
+        <Format as DecodeScalar<S>>::raw_decode(value, ctx).map(Action::Shell)
+    }
+}
+
+impl ToKdl for Format {
+    /// Escape what would otherwise be read as a variable again
+    fn to_kdl(&self) -> String {
+        self.source.replace("${", "$${").to_kdl()
+    }
+}
+
+/// Command given as a child node of a gesture, which is moved into the gesture's field of the
+/// same name once the config is read
+#[derive(Decode, Debug, Clone, PartialEq)]
+#[knuffel(span_type = Span)]
+pub enum ArgvNode {
+    Start(Argv),
+    Update(Argv),
+    End(Argv),
+    Action(Argv),
+    RepeatForward(Argv),
+    RepeatBackward(Argv),
+    Pipe(Argv),
+    WorkspaceProgress(Argv),
+    Cancel(Argv),
+}
+
+#[derive(Decode, Debug, Clone, PartialEq)]
+#[knuffel(span_type = Span)]
+pub struct Argv {
+    #[knuffel(arguments)]
+    pub args: Vec<Format>,
+    /// `session`, `system` or the address of a bus, for D-Bus calls
+    #[knuffel(property)]
+    pub bus: Option<String>,
+    #[knuffel(property)]
+    pub destination: Option<String>,
+    #[knuffel(property)]
+    pub path: Option<String>,
+    #[knuffel(property)]
+    pub interface: Option<String>,
+    /// Makes the command a D-Bus call of this method, with the arguments as its arguments
+    #[knuffel(property)]
+    pub method: Option<String>,
+    #[knuffel(property)]
+    pub signature: Option<String>,
+    /// Makes the command one sent to sway, like `workspace next`
+    #[knuffel(property)]
+    pub sway: Option<Format>,
+    /// Makes the command one sent to Hyprland, like `dispatch workspace e+1`
+    #[knuffel(property)]
+    pub hyprland: Option<Format>,
+    /// Socket of the compositor, found from the environment if not given
+    #[knuffel(property)]
+    pub socket: Option<String>,
+}
+
+impl Argv {
+    /// The command `name` given by this node: a D-Bus call if it has a `method`, a compositor
+    /// command if it has `sway` or `hyprland`, or the program to run along with its arguments
+    /// otherwise
+    pub fn into_action(self, name: &str) -> Result<Action, String> {
+        // Commands reading events from their input
+        let piped = matches!(name, "pipe" | "workspace-progress");
+        let compositor = match (self.sway, self.hyprland) {
+            (Some(_), Some(_)) => return Err("only one of `sway` and `hyprland` can be set".into()),
+            (Some(command), None) => Some((Compositor::Sway, command)),
+            (None, Some(command)) => Some((Compositor::Hyprland, command)),
+            (None, None) => None,
+        };
+        if let Some((compositor, command)) = compositor {
+            if piped {
+                return Err(format!(
+                    "`{name}` has to be a program, not a compositor command"
+                ));
+            }
+            if !self.args.is_empty() || self.method.is_some() {
+                return Err(format!(
+                    "a {} command takes no arguments or D-Bus properties",
+                    compositor.name()
+                ));
+            }
+            return Ok(Action::Compositor(Box::new(CompositorCommand {
+                compositor,
+                command,
+                socket: self.socket,
+            })));
+        }
+        if self.socket.is_some() {
+            return Err("`socket` is only used by `sway` and `hyprland` commands".to_string());
+        }
+        let Some(method) = self.method else {
+            let dbus_only = [
+                ("bus", &self.bus),
+                ("destination", &self.destination),
+                ("path", &self.path),
+                ("interface", &self.interface),
+                ("signature", &self.signature),
+            ];
+            if let Some((property, _)) = dbus_only.iter().find(|(_, v)| v.is_some()) {
+                return Err(format!(
+                    "`{property}` is only used by D-Bus calls, which need a `method`"
+                ));
+            }
+            if self.args.is_empty() {
+                return Err(format!("`{name}` needs at least the program to run"));
+            }
+            return Ok(Action::Argv(self.args));
+        };
+        if piped {
+            return Err(format!("`{name}` has to be a program, not a D-Bus call"));
+        }
+        let (Some(destination), Some(path)) = (self.destination, self.path) else {
+            return Err("a D-Bus call needs a `destination` and a `path`".to_string());
+        };
+        let call = DBusCall {
+            bus: self.bus.as_deref().unwrap_or("session").into(),
+            destination,
+            path,
+            interface: self.interface,
+            method,
+            signature: self.signature,
+            args: self.args,
+        };
+        call.validate()?;
+        Ok(Action::DBus(Box::new(call)))
+    }
+}
+
+impl ArgvNode {
+    /// Name of the node along with its arguments
+    pub fn into_parts(self) -> (&'static str, Argv) {
+        match self {
+            ArgvNode::Start(argv) => ("start", argv),
+            ArgvNode::Update(argv) => ("update", argv),
+            ArgvNode::End(argv) => ("end", argv),
+            ArgvNode::Action(argv) => ("action", argv),
+            ArgvNode::RepeatForward(argv) => ("repeat-forward", argv),
+            ArgvNode::RepeatBackward(argv) => ("repeat-backward", argv),
+            ArgvNode::Pipe(argv) => ("pipe", argv),
+            ArgvNode::WorkspaceProgress(argv) => ("workspace-progress", argv),
+            ArgvNode::Cancel(argv) => ("cancel", argv),
+        }
+    }
+}
@@ -1,9 +1,35 @@
-use knuffel::Decode;
+use std::fmt;
 
-#[derive(Decode, Debug, Clone, PartialEq, Eq)]
+use knuffel::{span::Span, Decode};
+
+use crate::config::Origin;
+use crate::gestures::action::Action;
+use crate::kdl::NodeWriter;
+use crate::window::WindowFilter;
+
+#[derive(Decode, Debug, Clone, PartialEq)]
+#[knuffel(span_type = Span)]
 pub struct Hold {
     #[knuffel(property)]
     pub fingers: i32,
     #[knuffel(property)]
-    pub action: Option<String>,
+    pub action: Option<Action>,
+    /// Milliseconds after which the commands are killed
+    #[knuffel(property)]
+    pub timeout: Option<u32>,
+    #[knuffel(flatten(property))]
+    pub window: WindowFilter,
+    #[knuffel(span)]
+    pub origin: Origin,
+}
+
+impl fmt::Display for Hold {
+    /// Write the hold as a KDL node
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let node = NodeWriter::new(f, "hold")
+            .property("fingers", Some(&self.fingers))
+            .command("action", self.action.as_ref())
+            .property("timeout", self.timeout.as_ref());
+        self.window.write_to(node).finish()
+    }
 }
@@ -1,17 +1,155 @@
+pub mod action;
 pub mod hold;
 pub mod pinch;
+pub mod stroke;
 pub mod swipe;
 
-use knuffel::Decode;
+use std::fmt;
+
+use knuffel::{ast::SpannedNode, decode::Context, errors::DecodeError, span::Span, Decode};
 
 use hold::Hold;
 use pinch::Pinch;
+use stroke::Stroke;
 use swipe::Swipe;
 
-#[derive(Decode, Debug, Clone, PartialEq)]
+use action::{Action, Argv, ArgvNode};
+
+use crate::config::Origin;
+use crate::window::WindowFilter;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Gesture {
-    Swipe(Swipe),
+    Swipe(Box<Swipe>),
+    Pinch(Pinch),
+    Hold(Hold),
+    Stroke(Stroke),
+    None,
+}
+
+/// A gesture node without the commands given as its child nodes
+#[derive(Decode)]
+#[knuffel(span_type = Span)]
+enum BareGesture {
+    Swipe(Box<Swipe>),
     Pinch(Pinch),
     Hold(Hold),
+    Stroke(Stroke),
     None,
 }
+
+impl Decode<Span> for Gesture {
+    /// Decode the node, and then each of its children into the field of the command it gives
+    fn decode_node(
+        node: &SpannedNode<Span>,
+        ctx: &mut Context<Span>,
+    ) -> Result<Self, DecodeError<Span>> {
+        let mut bare = node.clone();
+        let children = bare.children.take();
+        let mut gesture = match BareGesture::decode_node(&bare, ctx)? {
+            BareGesture::Swipe(g) => Gesture::Swipe(g),
+            BareGesture::Pinch(g) => Gesture::Pinch(g),
+            BareGesture::Hold(g) => Gesture::Hold(g),
+            BareGesture::Stroke(g) => Gesture::Stroke(g),
+            BareGesture::None => Gesture::None,
+        };
+        for child in children.iter().flat_map(|c| c.iter()) {
+            let (name, argv) = ArgvNode::decode_node(child, ctx)?.into_parts();
+            gesture
+                .set_command(name, argv)
+                .map_err(|e| DecodeError::unexpected(child, "node", e))?;
+        }
+        Ok(gesture)
+    }
+}
+
+impl Gesture {
+    pub fn window(&self) -> Option<&WindowFilter> {
+        match self {
+            Gesture::Swipe(g) => Some(&g.window),
+            Gesture::Pinch(g) => Some(&g.window),
+            Gesture::Hold(g) => Some(&g.window),
+            Gesture::Stroke(g) => Some(&g.window),
+            Gesture::None => None,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Gesture::Swipe(_) => "swipe",
+            Gesture::Pinch(_) => "pinch",
+            Gesture::Hold(_) => "hold",
+            Gesture::Stroke(_) => "stroke",
+            Gesture::None => "none",
+        }
+    }
+
+    /// Set the command `name` given by a child node
+    fn set_command(&mut self, name: &str, argv: Argv) -> Result<(), String> {
+        let kind = self.kind();
+        let Some(field) = self.command_mut(name) else {
+            return Err(format!("a {kind} has no `{name}` command"));
+        };
+        if field.is_some() {
+            return Err(format!("`{name}` is set more than once"));
+        }
+        *field = Some(argv.into_action(name)?);
+        Ok(())
+    }
+
+    fn command_mut(&mut self, name: &str) -> Option<&mut Option<Action>> {
+        match (self, name) {
+            (Gesture::Swipe(g), "start") => Some(&mut g.start),
+            (Gesture::Swipe(g), "update") => Some(&mut g.update),
+            (Gesture::Swipe(g), "end") => Some(&mut g.end),
+            (Gesture::Swipe(g), "repeat-forward") => Some(&mut g.repeat_forward),
+            (Gesture::Swipe(g), "repeat-backward") => Some(&mut g.repeat_backward),
+            (Gesture::Swipe(g), "pipe") => Some(&mut g.pipe),
+            (Gesture::Swipe(g), "workspace-progress") => Some(&mut g.workspace_progress),
+            (Gesture::Swipe(g), "cancel") => Some(&mut g.cancel),
+            (Gesture::Pinch(g), "start") => Some(&mut g.start),
+            (Gesture::Pinch(g), "update") => Some(&mut g.update),
+            (Gesture::Pinch(g), "end") => Some(&mut g.end),
+            (Gesture::Pinch(g), "pipe") => Some(&mut g.pipe),
+            (Gesture::Hold(g), "action") => Some(&mut g.action),
+            (Gesture::Stroke(g), "action") => Some(&mut g.action),
+            _ => None,
+        }
+    }
+
+    /// A copy of the gesture that is equal to the same gesture defined anywhere else
+    pub fn without_origin(&self) -> Gesture {
+        let mut gesture = self.clone();
+        match &mut gesture {
+            Gesture::Swipe(g) => g.origin = Origin::default(),
+            Gesture::Pinch(g) => g.origin = Origin::default(),
+            Gesture::Hold(g) => g.origin = Origin::default(),
+            Gesture::Stroke(g) => g.origin = Origin::default(),
+            Gesture::None => {}
+        }
+        gesture
+    }
+
+    pub fn origin(&self) -> Option<&Origin> {
+        match self {
+            Gesture::Swipe(g) => Some(&g.origin),
+            Gesture::Pinch(g) => Some(&g.origin),
+            Gesture::Hold(g) => Some(&g.origin),
+            Gesture::Stroke(g) => Some(&g.origin),
+            Gesture::None => None,
+        }
+    }
+}
+
+impl fmt::Display for Gesture {
+    /// Write the gesture as a KDL node
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Gesture::Swipe(g) => g.fmt(f),
+            Gesture::Pinch(g) => g.fmt(f),
+            Gesture::Hold(g) => g.fmt(f),
+            Gesture::Stroke(g) => g.fmt(f),
+            Gesture::None => f.write_str("none"),
+        }
+    }
+}
@@ -1,17 +1,54 @@
-use knuffel::{Decode, DecodeScalar};
+use std::fmt;
 
-#[derive(Decode, Debug, Clone, PartialEq, Eq)]
+use knuffel::{span::Span, Decode, DecodeScalar};
+
+use crate::config::Origin;
+use crate::gestures::action::Action;
+use crate::kdl::{NodeWriter, ToKdl};
+use crate::window::WindowFilter;
+
+#[derive(Decode, Debug, Clone, PartialEq)]
+#[knuffel(span_type = Span)]
 pub struct Pinch {
     #[knuffel(property)]
     pub fingers: i32,
     #[knuffel(property)]
     pub direction: PinchDir,
     #[knuffel(property)]
-    pub update: Option<String>,
+    pub update: Option<Action>,
+    #[knuffel(property)]
+    pub start: Option<Action>,
+    #[knuffel(property)]
+    pub end: Option<Action>,
+    /// Most times per second `update` runs
     #[knuffel(property)]
-    pub start: Option<String>,
+    pub max_rate: Option<u32>,
+    /// Command started once, which is sent every event of the gesture on its stdin
     #[knuffel(property)]
-    pub end: Option<String>,
+    pub pipe: Option<Action>,
+    /// Milliseconds after which the commands are killed
+    #[knuffel(property)]
+    pub timeout: Option<u32>,
+    #[knuffel(flatten(property))]
+    pub window: WindowFilter,
+    #[knuffel(span)]
+    pub origin: Origin,
+}
+
+impl fmt::Display for Pinch {
+    /// Write the pinch as a KDL node
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let node = NodeWriter::new(f, "pinch")
+            .property("direction", Some(&self.direction))
+            .property("fingers", Some(&self.fingers))
+            .command("start", self.start.as_ref())
+            .command("update", self.update.as_ref())
+            .command("end", self.end.as_ref())
+            .property("max-rate", self.max_rate.as_ref())
+            .command("pipe", self.pipe.as_ref())
+            .property("timeout", self.timeout.as_ref());
+        self.window.write_to(node).finish()
+    }
 }
 
 /// Direction of pinch gestures
@@ -25,6 +62,17 @@ pub enum PinchDir {
 }
 
 impl PinchDir {
+    /// Name of the direction in the config
+    pub fn name(&self) -> &'static str {
+        match self {
+            PinchDir::In => "in",
+            PinchDir::Out => "out",
+            PinchDir::Clockwise => "clockwise",
+            PinchDir::CounterClockwise => "counter-clockwise",
+            PinchDir::Any => "any",
+        }
+    }
+
     pub fn dir(scale: f64, delta_angle: f64) -> Self {
         // We have some rotation and very little scale
         if scale > 0.95 && scale < 1.05 && delta_angle.abs() > 0.03 {
@@ -41,3 +89,9 @@ impl PinchDir {
         }
     }
 }
+
+impl ToKdl for PinchDir {
+    fn to_kdl(&self) -> String {
+        self.name().to_kdl()
+    }
+}
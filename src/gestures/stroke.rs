@@ -0,0 +1,214 @@
+use std::{fmt, str::FromStr};
+
+use knuffel::{span::Span, Decode};
+
+use crate::config::Origin;
+use crate::gestures::action::Action;
+use crate::kdl::{NodeWriter, ToKdl};
+use crate::window::WindowFilter;
+
+/// Number of points every path is resampled to before comparing
+const RESAMPLE_POINTS: usize = 64;
+/// Size of the square paths are scaled into
+const SQUARE_SIZE: f64 = 250.0;
+/// Default minimum score for a stroke to be recognized
+pub const DEFAULT_MIN_SCORE: f64 = 0.8;
+
+#[derive(Decode, Debug, Clone, PartialEq)]
+#[knuffel(span_type = Span)]
+pub struct Stroke {
+    #[knuffel(property)]
+    pub fingers: i32,
+    #[knuffel(property)]
+    pub shape: String,
+    #[knuffel(property)]
+    pub action: Option<Action>,
+    #[knuffel(property)]
+    pub min_score: Option<f64>,
+    /// Milliseconds after which the commands are killed
+    #[knuffel(property)]
+    pub timeout: Option<u32>,
+    #[knuffel(flatten(property))]
+    pub window: WindowFilter,
+    #[knuffel(span)]
+    pub origin: Origin,
+}
+
+impl fmt::Display for Stroke {
+    /// Write the stroke as a KDL node
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let node = NodeWriter::new(f, "stroke")
+            .property("fingers", Some(&self.fingers))
+            .property("shape", Some(&self.shape))
+            .command("action", self.action.as_ref())
+            .property("min-score", self.min_score.as_ref())
+            .property("timeout", self.timeout.as_ref());
+        self.window.write_to(node).finish()
+    }
+}
+
+/// Named shape that strokes are matched against
+#[derive(Decode, Debug, Clone, PartialEq)]
+pub struct Template {
+    #[knuffel(argument)]
+    pub name: String,
+    #[knuffel(property, str)]
+    pub points: Points,
+}
+
+impl fmt::Display for Template {
+    /// Write the template as a KDL node
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        NodeWriter::new(f, "template")
+            .argument(&self.name)
+            .property("points", Some(&self.points))
+            .finish()
+    }
+}
+
+/// List of points, written as `"x,y x,y ..."` in the config
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Points(pub Vec<(f64, f64)>);
+
+impl FromStr for Points {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let points = s
+            .split_whitespace()
+            .map(|p| {
+                let (x, y) = p
+                    .split_once(',')
+                    .ok_or_else(|| format!("expected a point like `x,y`, found `{p}`"))?;
+                let x = x.trim().parse::<f64>().map_err(|e| format!("`{x}`: {e}"))?;
+                let y = y.trim().parse::<f64>().map_err(|e| format!("`{y}`: {e}"))?;
+                Ok((x, y))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if points.len() < 2 {
+            return Err("a template needs at least two points".to_string());
+        }
+        Ok(Self(points))
+    }
+}
+
+impl ToKdl for Points {
+    fn to_kdl(&self) -> String {
+        self.0
+            .iter()
+            .map(|(x, y)| format!("{x},{y}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_kdl()
+    }
+}
+
+/// Templates available without defining them in the config.
+/// Coordinates use the touchpad orientation, so y grows downwards.
+pub fn builtin_templates() -> Vec<Template> {
+    let circle = (0..=32)
+        .map(|i| {
+            let a = i as f64 / 32.0 * std::f64::consts::TAU;
+            (a.sin(), -a.cos())
+        })
+        .collect();
+    [
+        ("L", vec![(0.0, 0.0), (0.0, 1.0), (0.6, 1.0)]),
+        ("Z", vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)]),
+        ("V", vec![(0.0, 0.0), (0.5, 1.0), (1.0, 0.0)]),
+        ("circle", circle),
+    ]
+    .into_iter()
+    .map(|(name, points)| Template {
+        name: name.to_string(),
+        points: Points(points),
+    })
+    .collect()
+}
+
+/// Match `path` against `templates` using the $1 unistroke recognizer.
+///
+/// Unlike the original algorithm the path is not rotated to its indicative angle, since the
+/// orientation of a shape is what tells e.g. `L` apart from `Γ`. Returns the name and score
+/// (0.0..=1.0) of the best matching template.
+pub fn recognize<'a>(path: &[(f64, f64)], templates: &'a [Template]) -> Option<(&'a str, f64)> {
+    let candidate = normalize(path)?;
+    let half_diagonal = 0.5 * (2.0 * SQUARE_SIZE * SQUARE_SIZE).sqrt();
+
+    templates
+        .iter()
+        .filter_map(|t| {
+            let points = normalize(&t.points.0)?;
+            let distance = candidate
+                .iter()
+                .zip(&points)
+                .map(|(a, b)| (a.0 - b.0).hypot(a.1 - b.1))
+                .sum::<f64>()
+                / RESAMPLE_POINTS as f64;
+            Some((t.name.as_str(), 1.0 - distance / half_diagonal))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// Resample, scale and translate a path so it can be compared point by point
+fn normalize(path: &[(f64, f64)]) -> Option<Vec<(f64, f64)>> {
+    let points = resample(path)?;
+
+    let (min_x, max_x, min_y, max_y) = points.iter().fold(
+        (f64::MAX, f64::MIN, f64::MAX, f64::MIN),
+        |(min_x, max_x, min_y, max_y), &(x, y)| {
+            (min_x.min(x), max_x.max(x), min_y.min(y), max_y.max(y))
+        },
+    );
+    // Scale uniformly so straight lines don't blow up along their short axis
+    let scale = SQUARE_SIZE / (max_x - min_x).max(max_y - min_y);
+    let (cx, cy) = points
+        .iter()
+        .fold((0.0, 0.0), |(sx, sy), &(x, y)| (sx + x, sy + y));
+    let (cx, cy) = (cx / points.len() as f64, cy / points.len() as f64);
+
+    Some(
+        points
+            .into_iter()
+            .map(|(x, y)| ((x - cx) * scale, (y - cy) * scale))
+            .collect(),
+    )
+}
+
+/// Resample a path into `RESAMPLE_POINTS` equally spaced points
+fn resample(path: &[(f64, f64)]) -> Option<Vec<(f64, f64)>> {
+    let length: f64 = path
+        .windows(2)
+        .map(|w| (w[1].0 - w[0].0).hypot(w[1].1 - w[0].1))
+        .sum();
+    if length <= 0.0 {
+        return None;
+    }
+
+    let interval = length / (RESAMPLE_POINTS - 1) as f64;
+    let mut points = vec![path[0]];
+    let mut travelled = 0.0;
+    let mut prev = path[0];
+    for &next in &path[1..] {
+        let mut d = (next.0 - prev.0).hypot(next.1 - prev.1);
+        while travelled + d >= interval && d > 0.0 {
+            let t = (interval - travelled) / d;
+            let q = (
+                prev.0 + t * (next.0 - prev.0),
+                prev.1 + t * (next.1 - prev.1),
+            );
+            points.push(q);
+            prev = q;
+            d = (next.0 - prev.0).hypot(next.1 - prev.1);
+            travelled = 0.0;
+        }
+        travelled += d;
+        prev = next;
+    }
+    // Rounding errors can leave us one point short
+    while points.len() < RESAMPLE_POINTS {
+        points.push(*path.last().unwrap());
+    }
+    points.truncate(RESAMPLE_POINTS);
+    Some(points)
+}
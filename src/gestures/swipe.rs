@@ -1,21 +1,394 @@
-use knuffel::{Decode, DecodeScalar};
+use std::fmt;
 
-#[derive(Decode, Debug, Clone, PartialEq, Eq)]
+use knuffel::{
+    ast::{Literal, TypeName},
+    decode::Context,
+    errors::DecodeError,
+    span::{Span, Spanned},
+    traits::ErrorSpan,
+    Decode, DecodeScalar,
+};
+
+use crate::config::Origin;
+use crate::gestures::action::Action;
+use crate::kdl::{NodeWriter, ToKdl};
+use crate::window::WindowFilter;
+
+#[derive(Decode, Debug, Clone, PartialEq, Default)]
+#[knuffel(span_type = Span)]
 pub struct Swipe {
     #[knuffel(property)]
     pub direction: SwipeDir,
     #[knuffel(property)]
     pub fingers: i32,
     #[knuffel(property)]
-    pub update: Option<String>,
+    pub update: Option<Action>,
+    #[knuffel(property)]
+    pub start: Option<Action>,
+    #[knuffel(property)]
+    pub end: Option<Action>,
+    /// Distance (touchpad units) the swipe has to end away from where it began, in its
+    /// direction, for `end` to run
     #[knuffel(property)]
-    pub start: Option<String>,
+    pub commit_threshold: Option<u32>,
+    /// Runs instead of `end` when the swipe is cancelled or ends short of `commit-threshold`
     #[knuffel(property)]
-    pub end: Option<String>,
+    pub cancel: Option<Action>,
+    /// Most times per second `update` runs
+    #[knuffel(property)]
+    pub max_rate: Option<u32>,
+    /// Command started once, which is sent every event of the gesture on its stdin
+    #[knuffel(property)]
+    pub pipe: Option<Action>,
     #[knuffel(property)]
     pub acceleration: Option<i8>,
+    /// How the speed of the fingers scales the movement of a drag on top of `acceleration`
+    #[knuffel(property)]
+    pub acceleration_profile: Option<AccelProfile>,
+    /// Points of the `custom` acceleration profile
+    #[knuffel(property)]
+    pub acceleration_curve: Option<AccelCurve>,
+    /// Whether a drag follows the movement of the fingers before libinput's acceleration
+    #[knuffel(property)]
+    pub unaccelerated: Option<bool>,
     #[knuffel(property)]
     pub mouse_up_delay: Option<i64>,
+    /// Button held down while dragging, `left` if not given
+    #[knuffel(property)]
+    pub drag_button: Option<DragButton>,
+    /// Whether the button stays held when the fingers are lifted, until the next tap or gesture
+    #[knuffel(property)]
+    pub drag_lock: Option<bool>,
+    /// Width (pixels) of the edges of the screen where dragging scrolls
+    #[knuffel(property)]
+    pub edge_scroll: Option<u32>,
+    #[knuffel(property)]
+    pub speed: Option<SwipeSpeed>,
+    #[knuffel(property)]
+    pub min_velocity: Option<u32>,
+    #[knuffel(property)]
+    pub max_velocity: Option<u32>,
+    #[knuffel(property)]
+    pub repeat_every: Option<u32>,
+    #[knuffel(property)]
+    pub repeat_forward: Option<Action>,
+    #[knuffel(property)]
+    pub repeat_backward: Option<Action>,
+    /// Switch to the next or previous workspace along this axis
+    #[knuffel(property)]
+    pub workspace: Option<WorkspaceAxis>,
+    /// Distance (touchpad units) the fingers travel for a whole switch
+    #[knuffel(property)]
+    pub workspace_distance: Option<u32>,
+    /// Command sent the progress of the switch on its stdin, which then switches instead
+    #[knuffel(property)]
+    pub workspace_progress: Option<Action>,
+    /// Milliseconds after which the commands are killed
+    #[knuffel(property)]
+    pub timeout: Option<u32>,
+    #[knuffel(flatten(property))]
+    pub window: WindowFilter,
+    #[knuffel(span)]
+    pub origin: Origin,
+}
+
+impl Swipe {
+    /// Whether this swipe drags the cursor instead of running commands, which is done on Xorg
+    pub fn is_drag(&self) -> bool {
+        self.acceleration.is_some()
+            && self.mouse_up_delay.is_some()
+            && self.direction == SwipeDir::Any
+    }
+
+    /// How far a drag moves the pointer for a movement of `x` and `y` at `speed` (units per
+    /// second), in fractions of a pixel
+    pub fn drag_delta(&self, x: f64, y: f64, speed: f64) -> (f64, f64) {
+        let factor = self.acceleration.unwrap_or_default() as f64 / 10.0
+            * match (self.acceleration_profile, &self.acceleration_curve) {
+                (Some(AccelProfile::Adaptive), _) => AccelCurve::adaptive().factor(speed),
+                (Some(AccelProfile::Custom), Some(curve)) => curve.factor(speed),
+                _ => 1.0,
+            };
+        (x * factor, y * factor)
+    }
+
+    /// Button number held down while this swipe drags
+    pub fn drag_button(&self) -> i32 {
+        self.drag_button.map_or(1, DragButton::number)
+    }
+
+    /// How far a swipe that moved by `x` and `y` went in the direction of this gesture, which for
+    /// `any` is the distance from where it began
+    pub fn displacement(&self, x: f64, y: f64) -> f64 {
+        match self.direction {
+            SwipeDir::Any => x.hypot(y),
+            _ => {
+                let (ux, uy) = self.direction.unit_vector();
+                x * ux + y * uy
+            }
+        }
+    }
+
+    /// Whether a swipe that ended after moving by `x` and `y` went far enough to run `end`
+    pub fn committed(&self, x: f64, y: f64) -> bool {
+        self.commit_threshold.map_or(true, |threshold| {
+            self.displacement(x, y) >= threshold as f64
+        })
+    }
+
+    /// The direction the `repeat-every` steps of a swipe that began by moving `first` are
+    /// counted in, which for `any` is the one it began in
+    pub fn repeat_direction(&self, first: (f64, f64)) -> SwipeDir {
+        match self.direction {
+            SwipeDir::Any => SwipeDir::dir(first.0, first.1),
+            ref d => d.clone(),
+        }
+    }
+
+    /// Whether a swipe moving at `velocity` (units per second) should trigger this gesture
+    pub fn matches_velocity(&self, velocity: f64) -> bool {
+        let speed = match self.speed {
+            Some(SwipeSpeed::Fast) => velocity >= FAST_VELOCITY,
+            Some(SwipeSpeed::Slow) => velocity < FAST_VELOCITY,
+            None => true,
+        };
+        speed
+            && self.min_velocity.map_or(true, |min| velocity >= min as f64)
+            && self.max_velocity.map_or(true, |max| velocity <= max as f64)
+    }
+}
+
+/// How many `repeat-forward` and `repeat-backward` steps to run when a swipe that had run `prev`
+/// steps has gone `projection` in its direction, with a step every `every` units
+pub fn steps(prev: i64, projection: f64, every: u32) -> (u64, u64) {
+    let steps = (projection / every as f64).trunc() as i64;
+    if steps > prev {
+        (steps.abs_diff(prev), 0)
+    } else {
+        (0, prev.abs_diff(steps))
+    }
+}
+
+impl fmt::Display for Swipe {
+    /// Write the swipe as a KDL node
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let node = NodeWriter::new(f, "swipe")
+            .property("direction", Some(&self.direction))
+            .property("fingers", Some(&self.fingers))
+            .command("start", self.start.as_ref())
+            .command("update", self.update.as_ref())
+            .command("end", self.end.as_ref())
+            .property("commit-threshold", self.commit_threshold.as_ref())
+            .command("cancel", self.cancel.as_ref())
+            .property("max-rate", self.max_rate.as_ref())
+            .command("pipe", self.pipe.as_ref())
+            .property("acceleration", self.acceleration.as_ref())
+            .property("acceleration-profile", self.acceleration_profile.as_ref())
+            .property("acceleration-curve", self.acceleration_curve.as_ref())
+            .property("unaccelerated", self.unaccelerated.as_ref())
+            .property("mouse-up-delay", self.mouse_up_delay.as_ref())
+            .property("drag-button", self.drag_button.as_ref())
+            .property("drag-lock", self.drag_lock.as_ref())
+            .property("edge-scroll", self.edge_scroll.as_ref())
+            .property("speed", self.speed.as_ref())
+            .property("min-velocity", self.min_velocity.as_ref())
+            .property("max-velocity", self.max_velocity.as_ref())
+            .property("repeat-every", self.repeat_every.as_ref())
+            .command("repeat-forward", self.repeat_forward.as_ref())
+            .command("repeat-backward", self.repeat_backward.as_ref())
+            .property("workspace", self.workspace.as_ref())
+            .property("workspace-distance", self.workspace_distance.as_ref())
+            .command("workspace-progress", self.workspace_progress.as_ref())
+            .property("timeout", self.timeout.as_ref());
+        self.window.write_to(node).finish()
+    }
+}
+
+/// Velocity (units per second) above which a swipe counts as fast
+pub const FAST_VELOCITY: f64 = 800.0;
+
+/// Distance (touchpad units) of a whole workspace switch without `workspace-distance`
+pub const DEFAULT_WORKSPACE_DISTANCE: u32 = 300;
+/// Part of `workspace-distance` a swipe has to travel to switch when it ends
+pub const WORKSPACE_COMMIT: f64 = 0.5;
+
+/// Axis along which a `workspace` swipe switches
+#[derive(DecodeScalar, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkspaceAxis {
+    Horizontal,
+    Vertical,
+}
+
+impl WorkspaceAxis {
+    /// How far a swipe that moved by `x` and `y` is through a switch of `distance`, from -1
+    /// (previous workspace) to 1 (next). Moving the fingers left or up goes to the next one,
+    /// pulling it in like a page.
+    pub fn progress(self, x: f64, y: f64, distance: u32) -> f64 {
+        let travel = match self {
+            WorkspaceAxis::Horizontal => -x,
+            WorkspaceAxis::Vertical => -y,
+        };
+        (travel / distance.max(1) as f64).clamp(-1.0, 1.0)
+    }
+}
+
+impl ToKdl for WorkspaceAxis {
+    fn to_kdl(&self) -> String {
+        match self {
+            WorkspaceAxis::Horizontal => "horizontal",
+            WorkspaceAxis::Vertical => "vertical",
+        }
+        .to_kdl()
+    }
+}
+
+/// Acceleration of the pointer moved by a dragging swipe
+#[derive(DecodeScalar, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccelProfile {
+    /// The same factor at any speed
+    Flat,
+    /// Slow movements are more precise and fast ones go further, see [`AccelCurve::adaptive`]
+    Adaptive,
+    /// The factors of `acceleration-curve`
+    Custom,
+}
+
+impl ToKdl for AccelProfile {
+    fn to_kdl(&self) -> String {
+        match self {
+            AccelProfile::Flat => "flat",
+            AccelProfile::Adaptive => "adaptive",
+            AccelProfile::Custom => "custom",
+        }
+        .to_kdl()
+    }
+}
+
+/// Factors by speed (units per second), given as `"0:0.5 400:1 1500:2.5"`, which are
+/// interpolated between the points and held beyond the first and last
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccelCurve(pub Vec<(f64, f64)>);
+
+impl AccelCurve {
+    /// Curve of the `adaptive` profile
+    pub fn adaptive() -> Self {
+        Self(vec![(0.0, 0.5), (300.0, 1.0), (1000.0, 1.8), (2500.0, 3.0)])
+    }
+
+    /// Factor at `speed`
+    pub fn factor(&self, speed: f64) -> f64 {
+        let points = &self.0;
+        let Some(&(_, first)) = points.first() else {
+            return 1.0;
+        };
+        match points.iter().position(|&(s, _)| s > speed) {
+            Some(0) => first,
+            Some(i) => {
+                let ((s0, f0), (s1, f1)) = (points[i - 1], points[i]);
+                f0 + (f1 - f0) * (speed - s0) / (s1 - s0)
+            }
+            None => points[points.len() - 1].1,
+        }
+    }
+}
+
+impl std::str::FromStr for AccelCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut points: Vec<(f64, f64)> = Vec::new();
+        for point in s.split_whitespace() {
+            let parsed = point
+                .split_once(':')
+                .and_then(|(speed, factor)| Some((speed.parse().ok()?, factor.parse().ok()?)));
+            let Some((speed, factor)) = parsed else {
+                return Err(format!("`{point}` is not a point like `400:1.5`"));
+            };
+            if factor < 0.0 {
+                return Err(format!("the factor of `{point}` is negative"));
+            }
+            if points.last().is_some_and(|&(last, _)| speed <= last) {
+                return Err("the speeds of the points have to increase".to_string());
+            }
+            points.push((speed, factor));
+        }
+        if points.is_empty() {
+            return Err("the curve needs at least one point".to_string());
+        }
+        Ok(Self(points))
+    }
+}
+
+impl<S: ErrorSpan> DecodeScalar<S> for AccelCurve {
+    fn type_check(type_name: &Option<Spanned<TypeName, S>>, ctx: &mut Context<S>) {
+        <String as DecodeScalar<S>>::type_check(type_name, ctx)
+    }
+
+    fn raw_decode(
+        value: &Spanned<Literal, S>,
+        ctx: &mut Context<S>,
+    ) -> Result<Self, DecodeError<S>> {
+        let s = <String as DecodeScalar<S>>::raw_decode(value, ctx)?;
+        s.parse().map_err(|e| DecodeError::conversion(value, e))
+    }
+}
+
+impl ToKdl for AccelCurve {
+    fn to_kdl(&self) -> String {
+        self.0
+            .iter()
+            .map(|(speed, factor)| format!("{speed}:{factor}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_kdl()
+    }
+}
+
+/// Mouse button pressed by a dragging swipe
+#[derive(DecodeScalar, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DragButton {
+    Left,
+    Middle,
+    Right,
+}
+
+impl DragButton {
+    /// Number of the button for xdo
+    pub fn number(self) -> i32 {
+        match self {
+            DragButton::Left => 1,
+            DragButton::Middle => 2,
+            DragButton::Right => 3,
+        }
+    }
+}
+
+impl ToKdl for DragButton {
+    fn to_kdl(&self) -> String {
+        match self {
+            DragButton::Left => "left",
+            DragButton::Middle => "middle",
+            DragButton::Right => "right",
+        }
+        .to_kdl()
+    }
+}
+
+/// Speed of swipe gestures, see [`FAST_VELOCITY`]
+#[derive(DecodeScalar, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwipeSpeed {
+    Fast,
+    Slow,
+}
+
+impl ToKdl for SwipeSpeed {
+    fn to_kdl(&self) -> String {
+        match self {
+            SwipeSpeed::Fast => "fast",
+            SwipeSpeed::Slow => "slow",
+        }
+        .to_kdl()
+    }
 }
 
 /// Direction of swipe gestures
@@ -23,8 +396,9 @@ pub struct Swipe {
 /// NW  N  NE
 /// W   C   E
 /// SW  S  SE
-#[derive(DecodeScalar, Debug, Clone, PartialEq, Eq)]
+#[derive(DecodeScalar, Debug, Clone, PartialEq, Eq, Default)]
 pub enum SwipeDir {
+    #[default]
     Any,
     N,
     S,
@@ -37,6 +411,21 @@ pub enum SwipeDir {
 }
 
 impl SwipeDir {
+    /// Name of the direction in the config
+    pub fn name(&self) -> &'static str {
+        match self {
+            SwipeDir::Any => "any",
+            SwipeDir::N => "n",
+            SwipeDir::S => "s",
+            SwipeDir::E => "e",
+            SwipeDir::W => "w",
+            SwipeDir::NE => "ne",
+            SwipeDir::NW => "nw",
+            SwipeDir::SE => "se",
+            SwipeDir::SW => "sw",
+        }
+    }
+
     pub fn dir(x: f64, y: f64) -> SwipeDir {
         if x == 0.0 && y == 0.0 {
             return SwipeDir::Any;
@@ -66,4 +455,26 @@ impl SwipeDir {
             primary_direction
         }
     }
+
+    /// Unit vector pointing in this direction, or zero for `Any`
+    pub fn unit_vector(&self) -> (f64, f64) {
+        let d = std::f64::consts::FRAC_1_SQRT_2;
+        match self {
+            SwipeDir::Any => (0.0, 0.0),
+            SwipeDir::N => (0.0, -1.0),
+            SwipeDir::S => (0.0, 1.0),
+            SwipeDir::E => (1.0, 0.0),
+            SwipeDir::W => (-1.0, 0.0),
+            SwipeDir::NE => (d, -d),
+            SwipeDir::NW => (-d, -d),
+            SwipeDir::SE => (d, d),
+            SwipeDir::SW => (-d, d),
+        }
+    }
+}
+
+impl ToKdl for SwipeDir {
+    fn to_kdl(&self) -> String {
+        self.name().to_kdl()
+    }
 }
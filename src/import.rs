@@ -0,0 +1,421 @@
+//! Conversion of configs written for other gesture daemons
+
+use std::fmt::Write;
+
+use clap::ValueEnum;
+use miette::{
+    IntoDiagnostic, LabeledSpan, MietteDiagnostic, NamedSource, Report, Result, Severity,
+};
+use serde::Deserialize;
+use serde_yaml::Value;
+
+use crate::config::Config;
+use crate::gestures::{
+    action::Action,
+    hold::Hold,
+    pinch::{Pinch, PinchDir},
+    swipe::{Swipe, SwipeDir},
+};
+use crate::migrate::Migration;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// `libinput-gestures.conf`
+    LibinputGestures,
+    /// fusuma's `config.yml`
+    Fusuma,
+}
+
+pub fn import(format: ImportFormat, name: &str, text: &str) -> Result<Migration> {
+    let migration = match format {
+        ImportFormat::LibinputGestures => libinput_gestures(name, text),
+        ImportFormat::Fusuma => fusuma(name, text)?,
+    };
+    if let Err(e) = Config::parse("imported config", migration.kdl.clone()) {
+        return Err(e.wrap_err("The imported config could not be read back"));
+    }
+    Ok(migration)
+}
+
+/// Finger counts a gesture is bound to when the imported config doesn't say
+const SWIPE_FINGERS: [i32; 2] = [3, 4];
+const PINCH_FINGERS: [i32; 3] = [2, 3, 4];
+
+/// Convert lines like `gesture swipe left_up 3 xdotool key super+Left`, which run their command
+/// once the gesture ends
+fn libinput_gestures(name: &str, text: &str) -> Migration {
+    let mut kdl = format!("// Imported from {name} by `gestures import`\n");
+    let mut problems = Vec::new();
+    let mut unsupported = |offset: usize, line: &str, reason: &str| {
+        let diagnostic = MietteDiagnostic::new(format!("{reason}, left out"))
+            .with_severity(Severity::Warning)
+            .with_label(LabeledSpan::underline(offset..offset + line.len()));
+        problems.push(
+            Report::new(diagnostic).with_source_code(NamedSource::new(name, text.to_string())),
+        );
+    };
+
+    let mut offset = 0;
+    for raw in text.split_inclusive('\n') {
+        let line_offset = offset;
+        offset += raw.len();
+        let line = raw.trim();
+        let start = line_offset + raw.find(line).unwrap_or(0);
+        if line.is_empty() {
+            kdl.push('\n');
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix('#') {
+            writeln!(kdl, "//{comment}").unwrap();
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        if words.next() != Some("gesture") {
+            unsupported(start, line, "only `gesture` lines are supported");
+            writeln!(kdl, "// {line}").unwrap();
+            continue;
+        }
+        let (Some(kind), Some(direction)) = (words.next(), words.next()) else {
+            unsupported(start, line, "incomplete gesture");
+            writeln!(kdl, "// {line}").unwrap();
+            continue;
+        };
+        // Skip the words read so far to keep the spacing of the command
+        let mut rest = line;
+        for word in ["gesture", kind, direction] {
+            rest = rest[rest.find(word).unwrap() + word.len()..].trim_start();
+        }
+        let fingers_word = rest
+            .split_whitespace()
+            .next()
+            .filter(|w| w.parse::<i32>().is_ok());
+        let fingers: Option<i32> = fingers_word.and_then(|w| w.parse().ok());
+        if let Some(word) = fingers_word {
+            rest = rest[word.len()..].trim_start();
+        }
+        if rest.is_empty() || rest.starts_with("_internal") {
+            unsupported(start, line, "internal and empty commands are not supported");
+            writeln!(kdl, "// {line}").unwrap();
+            continue;
+        }
+        let command: Action = match rest.parse() {
+            Ok(command) => command,
+            Err(e) => {
+                unsupported(start, line, &e);
+                writeln!(kdl, "// {line}").unwrap();
+                continue;
+            }
+        };
+
+        let nodes: Vec<String> = match (kind, direction) {
+            ("swipe", direction) => {
+                let direction = match direction {
+                    "up" => SwipeDir::N,
+                    "down" => SwipeDir::S,
+                    "left" => SwipeDir::W,
+                    "right" => SwipeDir::E,
+                    "left_up" => SwipeDir::NW,
+                    "right_up" => SwipeDir::NE,
+                    "left_down" => SwipeDir::SW,
+                    "right_down" => SwipeDir::SE,
+                    _ => {
+                        unsupported(start, line, "unknown swipe direction");
+                        writeln!(kdl, "// {line}").unwrap();
+                        continue;
+                    }
+                };
+                fingers
+                    .as_ref()
+                    .map_or(&SWIPE_FINGERS[..], std::slice::from_ref)
+                    .iter()
+                    .map(|&fingers| {
+                        Swipe {
+                            direction: direction.clone(),
+                            fingers,
+                            end: Some(command.clone()),
+                            ..Default::default()
+                        }
+                        .to_string()
+                    })
+                    .collect()
+            }
+            ("pinch", direction) => {
+                let direction = match direction {
+                    "in" => PinchDir::In,
+                    "out" => PinchDir::Out,
+                    "clockwise" => PinchDir::Clockwise,
+                    "anticlockwise" => PinchDir::CounterClockwise,
+                    _ => {
+                        unsupported(start, line, "unknown pinch direction");
+                        writeln!(kdl, "// {line}").unwrap();
+                        continue;
+                    }
+                };
+                fingers
+                    .as_ref()
+                    .map_or(&PINCH_FINGERS[..], std::slice::from_ref)
+                    .iter()
+                    .map(|&fingers| pinch(fingers, direction.clone(), command.clone()))
+                    .collect()
+            }
+            ("hold", "on") => fingers
+                .as_ref()
+                .map_or(&SWIPE_FINGERS[..], std::slice::from_ref)
+                .iter()
+                .map(|&fingers| hold(fingers, command.clone()))
+                .collect(),
+            _ => {
+                unsupported(start, line, "unknown gesture");
+                writeln!(kdl, "// {line}").unwrap();
+                continue;
+            }
+        };
+        if fingers.is_none() {
+            writeln!(kdl, "// `{kind} {direction}` without a finger count").unwrap();
+        }
+        for node in nodes {
+            writeln!(kdl, "{node}").unwrap();
+        }
+    }
+
+    Migration { kdl, problems }
+}
+
+/// Convert fusuma's YAML, where commands are nested under the gesture, finger count and
+/// direction, e.g. `swipe: { 3: { left: { command: "..." } } }`
+fn fusuma(name: &str, text: &str) -> Result<Migration> {
+    let mut kdl = format!("// Imported from {name} by `gestures import`\n");
+    let mut problems = Vec::new();
+    let mut unsupported = |path: &str, reason: &str| {
+        let diagnostic = MietteDiagnostic::new(format!("{path}: {reason}, left out"))
+            .with_severity(Severity::Warning);
+        problems.push(Report::new(diagnostic));
+    };
+
+    // Documents after the first one only apply to some applications
+    let mut documents = serde_yaml::Deserializer::from_str(text);
+    let root = match documents.next() {
+        Some(document) => Value::deserialize(document).into_diagnostic()?,
+        None => Value::Null,
+    };
+    if documents.next().is_some() {
+        unsupported(
+            "config",
+            "documents for specific applications are not supported",
+        );
+    }
+
+    let Some(root) = root.as_mapping() else {
+        unsupported("config", "expected a mapping");
+        return Ok(Migration { kdl, problems });
+    };
+    for (kind, by_fingers) in root {
+        let kind = yaml_key(kind);
+        if !matches!(kind.as_str(), "swipe" | "pinch" | "rotate" | "hold") {
+            unsupported(&kind, "only gestures are supported");
+            continue;
+        }
+        let Some(by_fingers) = by_fingers.as_mapping() else {
+            unsupported(&kind, "expected finger counts");
+            continue;
+        };
+        for (fingers_key, by_direction) in by_fingers {
+            let path = format!("{kind}.{}", yaml_key(fingers_key));
+            let Some(fingers) = fingers_key.as_i64().and_then(|f| i32::try_from(f).ok()) else {
+                unsupported(&path, "expected a finger count");
+                continue;
+            };
+            let Some(by_direction) = by_direction.as_mapping() else {
+                unsupported(&path, "expected directions");
+                continue;
+            };
+
+            if kind == "hold" {
+                let action = fusuma_command(&path, by_direction, &mut unsupported);
+                if let Some(action) = action {
+                    writeln!(kdl, "{}", hold(fingers, action)).unwrap();
+                }
+                continue;
+            }
+
+            // `begin` and `end` next to the directions apply whichever way the swipe goes
+            let mut any = Swipe {
+                direction: SwipeDir::Any,
+                fingers,
+                ..Default::default()
+            };
+            for (direction, actions) in by_direction {
+                let direction = yaml_key(direction);
+                let path = format!("{path}.{direction}");
+                let Some(actions) = actions.as_mapping() else {
+                    unsupported(&path, "expected commands");
+                    continue;
+                };
+                if kind == "swipe" && matches!(direction.as_str(), "begin" | "end") {
+                    let command = fusuma_command(&path, actions, &mut unsupported);
+                    match direction.as_str() {
+                        "begin" => any.start = command,
+                        _ => any.end = command,
+                    }
+                    continue;
+                }
+                let (start, update, end) = fusuma_phases(&path, actions, &mut unsupported);
+                if start.is_none() && update.is_none() && end.is_none() {
+                    continue;
+                }
+                let node = match (kind.as_str(), direction.as_str()) {
+                    ("swipe", d @ ("up" | "down" | "left" | "right")) => Swipe {
+                        direction: match d {
+                            "up" => SwipeDir::N,
+                            "down" => SwipeDir::S,
+                            "left" => SwipeDir::W,
+                            _ => SwipeDir::E,
+                        },
+                        fingers,
+                        start,
+                        update,
+                        end,
+                        ..Default::default()
+                    }
+                    .to_string(),
+                    ("pinch", d @ ("in" | "out"))
+                    | ("rotate", d @ ("clockwise" | "counterclockwise")) => {
+                        let direction = match d {
+                            "in" => PinchDir::In,
+                            "out" => PinchDir::Out,
+                            "clockwise" => PinchDir::Clockwise,
+                            _ => PinchDir::CounterClockwise,
+                        };
+                        Pinch {
+                            fingers,
+                            direction,
+                            start,
+                            update,
+                            end,
+                            max_rate: None,
+                            pipe: None,
+                            timeout: None,
+                            window: Default::default(),
+                            origin: Default::default(),
+                        }
+                        .to_string()
+                    }
+                    _ => {
+                        unsupported(&path, "unknown direction");
+                        continue;
+                    }
+                };
+                writeln!(kdl, "{node}").unwrap();
+            }
+            if any.start.is_some() || any.end.is_some() {
+                writeln!(kdl, "{any}").unwrap();
+            }
+        }
+    }
+
+    Ok(Migration { kdl, problems })
+}
+
+/// The commands of a direction: `command` runs once the gesture is recognized, while `begin`,
+/// `update` and `end` hold commands of their own
+fn fusuma_phases(
+    path: &str,
+    actions: &serde_yaml::Mapping,
+    unsupported: &mut impl FnMut(&str, &str),
+) -> (Option<Action>, Option<Action>, Option<Action>) {
+    let (mut start, mut update, mut end) = (None, None, None);
+    for (key, value) in actions {
+        let key = yaml_key(key);
+        let path = format!("{path}.{key}");
+        let phase = match key.as_str() {
+            "command" => {
+                end = fusuma_action(&path, value, unsupported);
+                continue;
+            }
+            "begin" => &mut start,
+            "update" => &mut update,
+            "end" => &mut end,
+            // Only tune when commands run
+            "threshold" | "interval" => continue,
+            _ => {
+                unsupported(&path, "not supported");
+                continue;
+            }
+        };
+        *phase = value
+            .as_mapping()
+            .and_then(|m| fusuma_command(&path, m, unsupported));
+    }
+    (start, update, end)
+}
+
+/// The `command` of a mapping, reporting the keys that can't be imported
+fn fusuma_command(
+    path: &str,
+    actions: &serde_yaml::Mapping,
+    unsupported: &mut impl FnMut(&str, &str),
+) -> Option<Action> {
+    let mut command = None;
+    for (key, value) in actions {
+        match yaml_key(key).as_str() {
+            "command" => command = fusuma_action(&format!("{path}.command"), value, unsupported),
+            "threshold" | "interval" => {}
+            key => unsupported(&format!("{path}.{key}"), "not supported"),
+        }
+    }
+    command
+}
+
+fn fusuma_action(
+    path: &str,
+    value: &Value,
+    unsupported: &mut impl FnMut(&str, &str),
+) -> Option<Action> {
+    match value.as_str().map(str::parse) {
+        Some(Ok(action)) => Some(action),
+        Some(Err(e)) => {
+            unsupported(path, &e);
+            None
+        }
+        None => {
+            unsupported(path, "expected a command");
+            None
+        }
+    }
+}
+
+fn yaml_key(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        other => format!("{other:?}"),
+    }
+}
+
+fn pinch(fingers: i32, direction: PinchDir, end: Action) -> String {
+    Pinch {
+        fingers,
+        direction,
+        start: None,
+        update: None,
+        end: Some(end),
+        max_rate: None,
+        pipe: None,
+        timeout: None,
+        window: Default::default(),
+        origin: Default::default(),
+    }
+    .to_string()
+}
+
+fn hold(fingers: i32, action: Action) -> String {
+    Hold {
+        fingers,
+        action: Some(action),
+        timeout: None,
+        window: Default::default(),
+        origin: Default::default(),
+    }
+    .to_string()
+}
@@ -1,10 +1,13 @@
 use std::env;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Write};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::sync::{Arc, RwLock};
 use std::thread;
 
-use crate::config::Config;
+use crate::config::{Config, DEFAULT_PROFILE};
+use crate::executor::Executor;
+use crate::pipe::Pipes;
+use crate::xdo_handler;
 
 struct IpcListener(UnixListener);
 
@@ -16,17 +19,20 @@ impl Drop for IpcListener {
     }
 }
 
-pub fn create_socket(config: Arc<RwLock<Config>>) {
+pub fn create_socket(config: Arc<RwLock<Config>>, executor: Executor, pipes: Pipes) {
     let socket_dir = env::var("XDG_RUNTIME_DIR").unwrap_or("/tmp".to_string());
     let socket_path = format!("{}/gestures.sock", socket_dir);
     if std::path::Path::new(&socket_path).exists() {
         std::fs::remove_file(&socket_path).expect("Could not remove existing socket file");
     }
-    let listener = UnixListener::bind(&socket_path).unwrap();
+    let listener = IpcListener(UnixListener::bind(&socket_path).unwrap());
 
     {
         // let listener = listener.clone();
+        let pipes = pipes.clone();
         ctrlc::set_handler(move || {
+            xdo_handler::release_held_button();
+            pipes.stop();
             std::fs::remove_file(&socket_path).unwrap();
             std::process::exit(1);
         })
@@ -34,11 +40,13 @@ pub fn create_socket(config: Arc<RwLock<Config>>) {
     }
 
     // for stream in listener.read().unwrap().0.incoming() {
-    for stream in listener.incoming() {
+    for stream in listener.0.incoming() {
         match stream {
             Ok(stream) => {
                 let config = config.clone();
-                thread::spawn(|| handle_connection(stream, config));
+                let executor = executor.clone();
+                let pipes = pipes.clone();
+                thread::spawn(|| handle_connection(stream, config, executor, pipes));
             }
             Err(err) => {
                 eprintln!("Got error while handling IPC connection: {err}");
@@ -48,16 +56,65 @@ pub fn create_socket(config: Arc<RwLock<Config>>) {
     }
 }
 
-fn handle_connection(stream: UnixStream, config: Arc<RwLock<Config>>) {
+fn handle_connection(
+    stream: UnixStream,
+    config: Arc<RwLock<Config>>,
+    executor: Executor,
+    pipes: Pipes,
+) {
+    let mut writer = stream.try_clone().expect("Could not clone IPC stream");
     let stream = BufReader::new(stream);
 
     for line in stream.lines() {
-        if line.unwrap().contains("reload") {
+        let line = line.unwrap();
+        if line == "get-config" {
+            let dump = config.read().unwrap().to_string();
+            if let Err(e) = writer.write_all(dump.as_bytes()) {
+                log::error!("Could not send the config over IPC: {e}");
+            }
+        } else if line == "metrics" {
+            let metrics = executor.metrics().to_string();
+            if let Err(e) = writer.write_all(metrics.as_bytes()) {
+                log::error!("Could not send the metrics over IPC: {e}");
+            }
+        } else if let Some(name) = line.strip_prefix("profile ") {
             let mut c = config.write().unwrap();
-            *c = Config::read_default_config().unwrap_or_else(|_| {
-                log::error!("Could not read configuration file, using empty config!");
-                Config::default()
-            });
+            if name == DEFAULT_PROFILE || c.profiles.iter().any(|p| p.name == name) {
+                log::info!("Switching to profile {name}");
+                c.active_profile = name.to_string();
+                c.generation += 1;
+                pipes.retain_used(&c);
+            } else {
+                log::error!("No profile named {name}, keeping {}", c.active_profile());
+            }
+        } else if line.contains("reload") {
+            match Config::read_default_config().and_then(Config::check) {
+                Ok(new) => {
+                    let mut c = config.write().unwrap();
+                    let active_profile = std::mem::take(&mut c.active_profile);
+                    let generation = c.generation + 1;
+                    *c = new;
+                    c.generation = generation;
+                    let exists = active_profile.is_empty()
+                        || active_profile == DEFAULT_PROFILE
+                        || c.profiles.iter().any(|p| p.name == active_profile);
+                    if exists {
+                        c.active_profile = active_profile;
+                    } else {
+                        log::warn!(
+                            "Profile {active_profile} is gone from the configuration, \
+                             switching to {DEFAULT_PROFILE}"
+                        );
+                    }
+                    // Stop the pipes of gestures that the new configuration doesn't have
+                    pipes.retain_used(&c);
+                    executor.apply(&c.executor);
+                }
+                Err(e) => {
+                    log::error!("{e:?}");
+                    log::error!("Could not reload the configuration, keeping the current one");
+                }
+            }
         }
     }
 }
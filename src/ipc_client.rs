@@ -1,5 +1,6 @@
 use std::env;
-use std::io::Write;
+use std::io::{Read, Write};
+use std::net::Shutdown;
 use std::os::unix::net::UnixStream;
 
 use crate::Commands;
@@ -15,6 +16,25 @@ pub fn handle_command(cmd: Commands) {
         Commands::Reload => {
             stream.write_all(b"reload").unwrap();
         }
+        Commands::Profile { name } => {
+            stream
+                .write_all(format!("profile {name}").as_bytes())
+                .unwrap();
+        }
+        Commands::DumpConfig { .. } => {
+            stream.write_all(b"get-config\n").unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            let mut dump = String::new();
+            stream.read_to_string(&mut dump).unwrap();
+            print!("{dump}");
+        }
+        Commands::Metrics => {
+            stream.write_all(b"metrics\n").unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            let mut metrics = String::new();
+            stream.read_to_string(&mut metrics).unwrap();
+            print!("{metrics}");
+        }
         _ => (),
     }
 }
@@ -0,0 +1,132 @@
+use std::fmt;
+
+use crate::gestures::action::Action;
+
+/// Value that can be written as a KDL property or argument
+pub trait ToKdl {
+    fn to_kdl(&self) -> String;
+}
+
+impl ToKdl for str {
+    fn to_kdl(&self) -> String {
+        let mut s = String::with_capacity(self.len() + 2);
+        s.push('"');
+        for c in self.chars() {
+            match c {
+                '"' => s.push_str("\\\""),
+                '\\' => s.push_str("\\\\"),
+                '\n' => s.push_str("\\n"),
+                '\r' => s.push_str("\\r"),
+                '\t' => s.push_str("\\t"),
+                c => s.push(c),
+            }
+        }
+        s.push('"');
+        s
+    }
+}
+
+impl ToKdl for String {
+    fn to_kdl(&self) -> String {
+        self.as_str().to_kdl()
+    }
+}
+
+macro_rules! integer_to_kdl {
+    ($($t:ty),*) => {
+        $(impl ToKdl for $t {
+            fn to_kdl(&self) -> String {
+                self.to_string()
+            }
+        })*
+    };
+}
+
+integer_to_kdl!(i8, i32, i64, u32);
+
+impl ToKdl for bool {
+    fn to_kdl(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl ToKdl for f64 {
+    /// Always has a decimal point, since knuffel doesn't read integers into floats
+    fn to_kdl(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+/// Writes a node on a single line, leaving out properties that aren't set, followed by a block
+/// for its children if it has any
+pub struct NodeWriter<'a, 'b> {
+    f: &'a mut fmt::Formatter<'b>,
+    result: fmt::Result,
+    children: Vec<String>,
+}
+
+impl<'a, 'b> NodeWriter<'a, 'b> {
+    pub fn new(f: &'a mut fmt::Formatter<'b>, name: &str) -> Self {
+        let result = f.write_str(name);
+        Self {
+            f,
+            result,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn argument(mut self, value: &(impl ToKdl + ?Sized)) -> Self {
+        if self.result.is_ok() {
+            self.result = write!(self.f, " {}", value.to_kdl());
+        }
+        self
+    }
+
+    pub fn property(mut self, name: &str, value: Option<&(impl ToKdl + ?Sized)>) -> Self {
+        if let (Ok(()), Some(value)) = (self.result, value) {
+            self.result = write!(self.f, " {name}={}", value.to_kdl());
+        }
+        self
+    }
+
+    pub fn child(mut self, name: &str, arguments: &[impl ToKdl]) -> Self {
+        let mut child = name.to_string();
+        for argument in arguments {
+            child.push(' ');
+            child.push_str(&argument.to_kdl());
+        }
+        self.children.push(child);
+        self
+    }
+
+    /// Write a command as a property, or as a child node if it runs without a shell
+    pub fn command(self, name: &str, action: Option<&Action>) -> Self {
+        match action {
+            Some(Action::Shell(template)) => self.property(name, Some(template)),
+            Some(Action::Argv(args)) => self.child(name, args),
+            Some(Action::DBus(call)) => {
+                let mut writer = self;
+                writer.children.push(call.node(name));
+                writer
+            }
+            Some(Action::Compositor(command)) => {
+                let mut writer = self;
+                writer.children.push(command.node(name));
+                writer
+            }
+            None => self,
+        }
+    }
+
+    pub fn finish(self) -> fmt::Result {
+        self.result?;
+        if self.children.is_empty() {
+            return Ok(());
+        }
+        writeln!(self.f, " {{")?;
+        for child in &self.children {
+            writeln!(self.f, "    {child}")?;
+        }
+        write!(self.f, "}}")
+    }
+}
@@ -1,16 +1,25 @@
+mod compositor;
 mod config;
+mod dbus;
+mod environment;
 mod event_handler;
+mod executor;
 mod gestures;
+mod import;
 mod ipc;
 mod ipc_client;
-mod utils;
+mod kdl;
+mod migrate;
+mod pipe;
+mod validation;
+mod window;
 mod xdo_handler;
 
 #[cfg(test)]
 mod tests;
 
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{Arc, RwLock},
     thread::{self, JoinHandle},
 };
@@ -18,9 +27,11 @@ use std::{
 use clap::{Parser, Subcommand};
 use env_logger::Builder;
 use log::LevelFilter;
-use miette::Result;
+use miette::{bail, Result};
 
 use crate::config::*;
+use crate::executor::Executor;
+use crate::pipe::Pipes;
 use crate::xdo_handler::start_handler;
 
 fn main() -> Result<()> {
@@ -44,35 +55,100 @@ fn main() -> Result<()> {
         l.init();
     }
 
-    let c = if let Some(p) = app.conf {
-        Config::read_from_file(&p)?
+    match app.command {
+        Commands::Check { file } => check(file.or(app.conf)),
+        Commands::MigrateConfig { file } => migrate_config(&file),
+        Commands::Import { from, file } => import_config(from, &file),
+        // The running instance uses its own config, which the local one may not even match
+        c @ (Commands::Reload
+        | Commands::Profile { .. }
+        | Commands::DumpConfig { daemon: true }
+        | Commands::Metrics) => {
+            ipc_client::handle_command(c);
+            Ok(())
+        }
+        Commands::DumpConfig { daemon: false } => {
+            print!("{}", read_config(app.conf)?);
+            Ok(())
+        }
+        Commands::Start => {
+            let config = Arc::new(RwLock::new(read_config(app.conf)?));
+            run_eh(config, app.wayland_disp)
+        }
+    }
+}
+
+/// Read the config the daemon would use, falling back to an empty one if the default file can't
+/// be read
+fn read_config(file: Option<PathBuf>) -> Result<Config> {
+    let c = if let Some(p) = file {
+        Config::read_from_file(&p)?.check()?
     } else {
-        config::Config::read_default_config().unwrap_or_else(|_| {
-            log::error!("Could not read configuration file, using empty config!");
-            Config::default()
-        })
+        config::Config::read_default_config()
+            .unwrap_or_else(|e| {
+                log::error!("{e:?}");
+                log::error!("Could not read configuration file, using empty config!");
+                Config::default()
+            })
+            .check()?
     };
     log::debug!("{:#?}", &c);
+    Ok(c)
+}
 
-    match app.command {
-        c @ Commands::Reload => {
-            ipc_client::handle_command(c);
-        }
-        Commands::Start => run_eh(Arc::new(RwLock::new(c)), app.wayland_disp)?,
+/// Print every problem found in the config, failing if any of them is an error
+fn check(file: Option<PathBuf>) -> Result<()> {
+    let config = match &file {
+        Some(p) => Config::read_from_file(p)?,
+        None => Config::read_default_config()?,
+    };
+
+    let reports = validation::validate(&config);
+    for report in &reports {
+        eprintln!("{report:?}");
     }
+    if validation::has_errors(&reports) {
+        bail!("The configuration has errors");
+    }
+    println!("The configuration is valid");
+    Ok(())
+}
 
+/// Print the KDL equivalent of a config from before 0.5.0
+fn migrate_config(file: &Path) -> Result<()> {
+    let text = std::fs::read_to_string(file)
+        .map_err(|e| miette::miette!("Could not read {}: {e}", file.display()))?;
+    print_migration(migrate::migrate(&file.to_string_lossy(), &text)?);
     Ok(())
 }
 
+/// Print the KDL equivalent of a config written for another program
+fn import_config(format: import::ImportFormat, file: &Path) -> Result<()> {
+    let text = std::fs::read_to_string(file)
+        .map_err(|e| miette::miette!("Could not read {}: {e}", file.display()))?;
+    print_migration(import::import(format, &file.to_string_lossy(), &text)?);
+    Ok(())
+}
+
+fn print_migration(migration: migrate::Migration) {
+    for problem in &migration.problems {
+        eprintln!("{problem:?}");
+    }
+    print!("{}", migration.kdl);
+}
+
 fn run_eh(config: Arc<RwLock<Config>>, is_wayland: bool) -> Result<()> {
     let eh_thread: JoinHandle<Result<()>>;
+    let executor = Executor::new(&config.read().unwrap().executor);
+    let pipes = Pipes::default();
 
     {
         let config = config.clone();
-        let is_wayland = is_wayland.clone();
+        let executor = executor.clone();
+        let pipes = pipes.clone();
         eh_thread = thread::spawn(move || -> Result<()> {
             log::debug!("Starting event handler in new thread");
-            let mut eh = event_handler::EventHandler::new(config);
+            let mut eh = event_handler::EventHandler::new(config, executor, pipes);
             let mut interface = input::Libinput::new_with_udev(event_handler::Interface);
             eh.init(&mut interface)?;
             eh.main_loop(&mut interface, &mut start_handler(!is_wayland));
@@ -80,7 +156,7 @@ fn run_eh(config: Arc<RwLock<Config>>, is_wayland: bool) -> Result<()> {
         });
     }
 
-    ipc::create_socket(config);
+    ipc::create_socket(config, executor, pipes);
 
     eh_thread.join().unwrap()?;
     Ok(())
@@ -110,6 +186,37 @@ struct App {
 pub enum Commands {
     /// Reload the configuration
     Reload,
+    /// Switch to another profile of the configuration
+    Profile {
+        /// Name of the profile
+        name: String,
+    },
     /// Start the program
     Start,
+    /// Check the configuration for mistakes without starting
+    Check {
+        /// Config file to check instead of the default one
+        file: Option<PathBuf>,
+    },
+    /// Print the configuration as KDL, with includes and drop-in files merged
+    DumpConfig {
+        /// Ask the running instance for the configuration it is using
+        #[arg(long)]
+        daemon: bool,
+    },
+    /// Print the number of commands waiting and running in the running instance
+    Metrics,
+    /// Convert a configuration from before 0.5.0 to KDL, printing the result
+    MigrateConfig {
+        /// Old configuration file
+        file: PathBuf,
+    },
+    /// Convert the configuration of another gesture program to KDL, printing the result
+    Import {
+        /// Program the configuration was written for
+        #[arg(long, value_enum)]
+        from: import::ImportFormat,
+        /// Configuration file to convert
+        file: PathBuf,
+    },
 }
@@ -0,0 +1,597 @@
+//! Conversion of the RON configuration used before 0.5.0, which looked like
+//!
+//! ```text
+//! (
+//!     device: None,
+//!     gestures: [
+//!         // Go back
+//!         Swipe(direction: W, fingers: 3, action: "xdotool key alt+Right"),
+//!         Pinch(direction: In, fingers: 4, start: "...", update: "...", end: "..."),
+//!         Hold(fingers: 4, action: "..."),
+//!     ],
+//! )
+//! ```
+
+use std::fmt::Write;
+
+use miette::{LabeledSpan, MietteDiagnostic, NamedSource, Report, Result, Severity};
+
+use crate::config::Config;
+use crate::gestures::{
+    action::Action,
+    hold::Hold,
+    pinch::{Pinch, PinchDir},
+    swipe::{Swipe, SwipeDir},
+};
+
+/// KDL text converted from another config format, with warnings about what couldn't be carried
+/// over
+pub struct Migration {
+    pub kdl: String,
+    pub problems: Vec<Report>,
+}
+
+pub fn migrate(name: &str, text: &str) -> Result<Migration> {
+    let source = || NamedSource::new(name, text.to_string());
+    let (tokens, comments) = lex(text).map_err(|e| e.into_report().with_source_code(source()))?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let root = parser
+        .value()
+        .and_then(|v| match parser.tokens.get(parser.pos) {
+            None => Ok(v),
+            Some(t) => Err(Problem::new(t.span, "expected the end of the file")),
+        })
+        .map_err(|e| e.into_report().with_source_code(source()))?;
+
+    let mut problems = Vec::new();
+    let mut elements: &[Spanned] = &[];
+    match &root.value {
+        Value::Struct { fields, .. } => {
+            for (key, value) in fields {
+                match (key.as_deref(), &value.value) {
+                    (Some("gestures"), Value::List(items)) => elements = items,
+                    (Some("device"), Value::Ident(i)) if i == "None" => {}
+                    (Some("device"), _) => problems.push(Problem::new(
+                        value.span,
+                        "`device` is not supported anymore, all touchpads are used",
+                    )),
+                    _ => problems.push(Problem::new(value.span, "unknown setting, skipped")),
+                }
+            }
+        }
+        _ => problems.push(Problem::new(
+            root.span,
+            "expected `(device: ..., gestures: [...])`",
+        )),
+    }
+
+    let mut kdl = format!("// Migrated from {name} by `gestures migrate-config`\n");
+    let mut comments = comments.into_iter().peekable();
+    for element in elements {
+        while let Some(c) = comments.next_if(|c| c.span.0 < element.span.1) {
+            writeln!(kdl, "{}", c.text).unwrap();
+        }
+        let trailing = comments.next_if(|c| {
+            c.text.starts_with("//") && !text[element.span.1..c.span.0].contains('\n')
+        });
+
+        let mut element_problems = Vec::new();
+        match convert(element, &mut element_problems) {
+            Some(node) => {
+                for p in &element_problems {
+                    writeln!(kdl, "// migrate-config: {}", p.message).unwrap();
+                }
+                match trailing {
+                    Some(c) => writeln!(kdl, "{node} {}", c.text).unwrap(),
+                    None => writeln!(kdl, "{node}").unwrap(),
+                }
+            }
+            None => {
+                for p in &element_problems {
+                    writeln!(kdl, "// migrate-config: {}", p.message).unwrap();
+                }
+                for line in text[element.span.0..element.span.1].lines() {
+                    writeln!(kdl, "// {}", line.trim()).unwrap();
+                }
+                if let Some(c) = trailing {
+                    writeln!(kdl, "{}", c.text).unwrap();
+                }
+            }
+        }
+        problems.extend(element_problems);
+    }
+    for c in comments {
+        writeln!(kdl, "{}", c.text).unwrap();
+    }
+
+    if let Err(e) = Config::parse("migrated config", kdl.clone()) {
+        return Err(e.wrap_err("The migrated config could not be read back"));
+    }
+
+    Ok(Migration {
+        kdl,
+        problems: problems
+            .into_iter()
+            .map(|p| {
+                Report::new(p.into_diagnostic().with_severity(Severity::Warning))
+                    .with_source_code(source())
+            })
+            .collect(),
+    })
+}
+
+/// Convert one entry of the `gestures` list into a KDL node, or `None` if it can't be
+fn convert(element: &Spanned, problems: &mut Vec<Problem>) -> Option<String> {
+    let Value::Struct {
+        name: Some(kind),
+        fields,
+    } = &element.value
+    else {
+        problems.push(Problem::new(element.span, "expected a gesture, left out"));
+        return None;
+    };
+
+    let mut direction = None;
+    let mut fingers = None;
+    let mut action = None;
+    let (mut start, mut update, mut end) = (None, None, None);
+    let (mut acceleration, mut mouse_up_delay) = (None, None);
+    for (key, value) in fields {
+        let value_span = value.span;
+        let value = match &value.value {
+            // Optional fields may be written as `Some(...)`
+            Value::Struct {
+                name: Some(some),
+                fields,
+            } if some == "Some" && fields.len() == 1 && fields[0].0.is_none() => &fields[0].1,
+            Value::Ident(none) if none == "None" => continue,
+            _ => value,
+        };
+        let Some(key) = key else {
+            problems.push(Problem::new(value_span, "unnamed field, left out"));
+            continue;
+        };
+        let parsed = match key.as_str() {
+            "direction" => value.ident().map(|i| direction = Some((i, value_span))),
+            "fingers" => value.int().map(|i| fingers = Some(i)),
+            "action" => value
+                .string()
+                .and_then(|s| s.parse().ok())
+                .map(|a| action = Some(a)),
+            "start" => value
+                .string()
+                .and_then(|s| s.parse().ok())
+                .map(|a| start = Some(a)),
+            "update" => value
+                .string()
+                .and_then(|s| s.parse().ok())
+                .map(|a| update = Some(a)),
+            "end" => value
+                .string()
+                .and_then(|s| s.parse().ok())
+                .map(|a| end = Some(a)),
+            "acceleration" => value
+                .int()
+                .and_then(|i| i8::try_from(i).ok())
+                .map(|i| acceleration = Some(i)),
+            "mouse_up_delay" => value.int().map(|i| mouse_up_delay = Some(i)),
+            _ => {
+                problems.push(Problem::new(
+                    value_span,
+                    format!("unknown field `{key}` of {kind}, left out"),
+                ));
+                continue;
+            }
+        };
+        if parsed.is_none() {
+            problems.push(Problem::new(
+                value_span,
+                format!("unexpected value for `{key}`, left out"),
+            ));
+        }
+    }
+
+    let Some(fingers) = fingers.and_then(|f| i32::try_from(f).ok()) else {
+        problems.push(Problem::new(
+            element.span,
+            format!("{kind} without a valid finger count, left out"),
+        ));
+        return None;
+    };
+    // `action` was the only command before `start`, `update` and `end` were added
+    let mut action_to_end = |end: &mut Option<Action>| {
+        if let Some(action) = action.take() {
+            if end.is_some() {
+                problems.push(Problem::new(
+                    element.span,
+                    "both `action` and `end` are set, only `end` was kept",
+                ));
+            } else {
+                *end = Some(action);
+            }
+        }
+    };
+
+    match kind.as_str() {
+        "Swipe" => {
+            action_to_end(&mut end);
+            let direction = match direction {
+                Some((d, span)) => match d.to_lowercase().as_str() {
+                    "any" => SwipeDir::Any,
+                    "n" => SwipeDir::N,
+                    "s" => SwipeDir::S,
+                    "e" => SwipeDir::E,
+                    "w" => SwipeDir::W,
+                    "ne" => SwipeDir::NE,
+                    "nw" => SwipeDir::NW,
+                    "se" => SwipeDir::SE,
+                    "sw" => SwipeDir::SW,
+                    _ => {
+                        problems.push(Problem::new(span, format!("unknown direction `{d}`")));
+                        return None;
+                    }
+                },
+                None => SwipeDir::Any,
+            };
+            let swipe = Swipe {
+                direction,
+                fingers,
+                start,
+                update,
+                end,
+                acceleration,
+                mouse_up_delay,
+                ..Default::default()
+            };
+            Some(swipe.to_string())
+        }
+        "Pinch" => {
+            action_to_end(&mut end);
+            let direction = match direction {
+                Some((d, span)) => match d.to_lowercase().as_str() {
+                    "in" => PinchDir::In,
+                    "out" => PinchDir::Out,
+                    "clockwise" => PinchDir::Clockwise,
+                    "counterclockwise" => PinchDir::CounterClockwise,
+                    "any" => PinchDir::Any,
+                    _ => {
+                        problems.push(Problem::new(span, format!("unknown direction `{d}`")));
+                        return None;
+                    }
+                },
+                None => PinchDir::Any,
+            };
+            let pinch = Pinch {
+                fingers,
+                direction,
+                start,
+                update,
+                end,
+                max_rate: None,
+                pipe: None,
+                timeout: None,
+                window: Default::default(),
+                origin: Default::default(),
+            };
+            Some(pinch.to_string())
+        }
+        "Hold" => {
+            let hold = Hold {
+                fingers,
+                action,
+                timeout: None,
+                window: Default::default(),
+                origin: Default::default(),
+            };
+            Some(hold.to_string())
+        }
+        _ => {
+            problems.push(Problem::new(
+                element.span,
+                format!("{kind} gestures don't exist anymore, left out"),
+            ));
+            None
+        }
+    }
+}
+
+/// Something that went wrong while migrating, pointing at the legacy config
+struct Problem {
+    span: (usize, usize),
+    message: String,
+}
+
+impl Problem {
+    fn new(span: (usize, usize), message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+
+    fn into_diagnostic(self) -> MietteDiagnostic {
+        let label = LabeledSpan::underline(self.span.0..self.span.1);
+        MietteDiagnostic::new(self.message).with_label(label)
+    }
+
+    fn into_report(self) -> Report {
+        Report::new(self.into_diagnostic())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open(char),
+    Close(char),
+    Colon,
+    Comma,
+    Ident(String),
+    Str(String),
+    Num(String),
+}
+
+#[derive(Clone)]
+struct SpannedToken {
+    token: Token,
+    span: (usize, usize),
+}
+
+struct Comment {
+    text: String,
+    span: (usize, usize),
+}
+
+enum Value {
+    Str(String),
+    Num(String),
+    Ident(String),
+    /// `Name(a: 1, b: 2)`, `(a: 1)` or `Some(1)`, where positional fields have no name
+    Struct {
+        name: Option<String>,
+        fields: Vec<(Option<String>, Spanned)>,
+    },
+    List(Vec<Spanned>),
+}
+
+struct Spanned {
+    value: Value,
+    span: (usize, usize),
+}
+
+impl Spanned {
+    fn ident(&self) -> Option<String> {
+        match &self.value {
+            Value::Ident(i) => Some(i.clone()),
+            _ => None,
+        }
+    }
+
+    fn int(&self) -> Option<i64> {
+        match &self.value {
+            Value::Num(n) => n.replace('_', "").parse().ok(),
+            _ => None,
+        }
+    }
+
+    fn string(&self) -> Option<String> {
+        match &self.value {
+            Value::Str(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Split the text into tokens, keeping comments apart
+fn lex(text: &str) -> Result<(Vec<SpannedToken>, Vec<Comment>), Problem> {
+    let mut tokens = Vec::new();
+    let mut comments = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '/' if chars.next_if(|&(_, c)| c == '/').is_some() => {
+                let end = text[start..].find('\n').map_or(text.len(), |i| start + i);
+                while chars.next_if(|&(i, _)| i < end).is_some() {}
+                comments.push(Comment {
+                    text: text[start..end].trim_end().to_string(),
+                    span: (start, end),
+                });
+                continue;
+            }
+            '/' if chars.next_if(|&(_, c)| c == '*').is_some() => {
+                let mut depth = 1;
+                let mut end = None;
+                while let Some((i, c)) = chars.next() {
+                    if c == '*' && chars.next_if(|&(_, c)| c == '/').is_some() {
+                        depth -= 1;
+                        if depth == 0 {
+                            end = Some(i + 2);
+                            break;
+                        }
+                    } else if c == '/' && chars.next_if(|&(_, c)| c == '*').is_some() {
+                        depth += 1;
+                    }
+                }
+                let Some(end) = end else {
+                    return Err(Problem::new((start, text.len()), "unclosed comment"));
+                };
+                comments.push(Comment {
+                    text: text[start..end].to_string(),
+                    span: (start, end),
+                });
+                continue;
+            }
+            '(' | '[' | '{' => Token::Open(c),
+            ')' | ']' | '}' => Token::Close(c),
+            ':' => Token::Colon,
+            ',' => Token::Comma,
+            '"' => Token::Str(lex_string(text, start, &mut chars)?),
+            'r' if matches!(chars.peek(), Some((_, '"' | '#'))) => {
+                let mut hashes = 0;
+                while chars.next_if(|&(_, c)| c == '#').is_some() {
+                    hashes += 1;
+                }
+                if chars.next_if(|&(_, c)| c == '"').is_none() {
+                    return Err(Problem::new((start, start + 1), "invalid raw string"));
+                }
+                let body_start = start + 2 + hashes;
+                let close = format!("\"{}", "#".repeat(hashes));
+                let Some(len) = text[body_start..].find(&close) else {
+                    return Err(Problem::new((start, text.len()), "unclosed string"));
+                };
+                let end = body_start + len + close.len();
+                while chars.next_if(|&(i, _)| i < end).is_some() {}
+                Token::Str(text[body_start..body_start + len].to_string())
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '+' => {
+                let mut end = start + 1;
+                while let Some((i, _)) =
+                    chars.next_if(|&(_, c)| c.is_ascii_alphanumeric() || matches!(c, '_' | '.'))
+                {
+                    end = i + 1;
+                }
+                Token::Num(text[start..end].to_string())
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|&(_, c)| c.is_alphanumeric() || c == '_') {
+                    end = i + c.len_utf8();
+                }
+                Token::Ident(text[start..end].to_string())
+            }
+            c => {
+                return Err(Problem::new(
+                    (start, start + c.len_utf8()),
+                    format!("unexpected character `{c}`"),
+                ))
+            }
+        };
+        let end = chars.peek().map_or(text.len(), |&(i, _)| i);
+        tokens.push(SpannedToken {
+            token,
+            span: (start, end),
+        });
+    }
+    Ok((tokens, comments))
+}
+
+/// Read a string whose opening quote at `start` was just consumed
+fn lex_string(
+    text: &str,
+    start: usize,
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+) -> Result<String, Problem> {
+    let mut s = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok(s),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => s.push('\n'),
+                Some((_, 't')) => s.push('\t'),
+                Some((_, 'r')) => s.push('\r'),
+                Some((_, '0')) => s.push('\0'),
+                Some((_, c @ ('"' | '\\' | '\''))) => s.push(c),
+                _ => return Err(Problem::new((i, i + 2), "unknown escape sequence")),
+            },
+            c => s.push(c),
+        }
+    }
+    Err(Problem::new((start, text.len()), "unclosed string"))
+}
+
+struct Parser {
+    tokens: Vec<SpannedToken>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    fn next(&mut self) -> Result<SpannedToken, Problem> {
+        let end = self.tokens.last().map_or(0, |t| t.span.1);
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or_else(|| Problem::new((end, end), "unexpected end of file"))?
+            .clone();
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn value(&mut self) -> Result<Spanned, Problem> {
+        let token = self.next()?;
+        let start = token.span.0;
+        let mut end = token.span.1;
+        let value = match token.token {
+            Token::Str(s) => Value::Str(s),
+            Token::Num(n) => Value::Num(n),
+            Token::Ident(i) if self.peek() == Some(&Token::Open('(')) => {
+                let name = Some(i);
+                self.pos += 1;
+                let fields;
+                (fields, end) = self.fields()?;
+                Value::Struct { name, fields }
+            }
+            Token::Ident(i) => Value::Ident(i),
+            Token::Open('(') => {
+                let fields;
+                (fields, end) = self.fields()?;
+                Value::Struct { name: None, fields }
+            }
+            Token::Open('[') => {
+                let mut items = Vec::new();
+                loop {
+                    if let Some(Token::Close(']')) = self.peek() {
+                        end = self.next()?.span.1;
+                        break;
+                    }
+                    items.push(self.value()?);
+                    let token = self.next()?;
+                    match token.token {
+                        Token::Comma => {}
+                        Token::Close(']') => {
+                            end = token.span.1;
+                            break;
+                        }
+                        _ => return Err(Problem::new(token.span, "expected `,` or `]`")),
+                    }
+                }
+                Value::List(items)
+            }
+            _ => return Err(Problem::new(token.span, "expected a value")),
+        };
+        Ok(Spanned {
+            value,
+            span: (start, end),
+        })
+    }
+
+    /// Fields of a struct whose opening parenthesis was just consumed, and where it ends
+    #[allow(clippy::type_complexity)]
+    fn fields(&mut self) -> Result<(Vec<(Option<String>, Spanned)>, usize), Problem> {
+        let mut fields = Vec::new();
+        loop {
+            if let Some(Token::Close(')')) = self.peek() {
+                return Ok((fields, self.next()?.span.1));
+            }
+            let key = match (self.peek(), self.tokens.get(self.pos + 1).map(|t| &t.token)) {
+                (Some(Token::Ident(key)), Some(Token::Colon)) => {
+                    let key = key.clone();
+                    self.pos += 2;
+                    Some(key)
+                }
+                _ => None,
+            };
+            fields.push((key, self.value()?));
+            let token = self.next()?;
+            match token.token {
+                Token::Comma => {}
+                Token::Close(')') => return Ok((fields, token.span.1)),
+                _ => return Err(Problem::new(token.span, "expected `,` or `)`")),
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Long-running commands that are sent the events of gestures on their stdin, one line each
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Child, Stdio};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::environment::CommandEnv;
+use crate::gestures::{
+    action::{Action, Values},
+    Gesture,
+};
+
+/// Lines that can wait for a slow reader before new ones are dropped
+const BUFFERED_LINES: usize = 256;
+/// Least time between two starts of the same command, so one that exits right away isn't
+/// restarted in a loop
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+/// How long a command that is no longer used may take to exit once its input is closed
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Handle to the running pipe commands, cheap to clone
+#[derive(Debug, Clone, Default)]
+pub struct Pipes {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Input of the running commands, by command line
+    pipes: HashMap<String, SyncSender<String>>,
+    /// Threads feeding the commands, which finish once their command has exited
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Pipes {
+    /// Write `phase` and `values` to the process of `action`, starting it with `env` if needed
+    pub fn send(&self, action: &Action, phase: &str, values: &Values, env: &CommandEnv) {
+        let line = format!(
+            "{phase} {} {} {} {} {} {} {} {}",
+            values.delta_x,
+            values.delta_y,
+            values.scale,
+            values.delta_angle,
+            values.fingers,
+            values.direction,
+            values.velocity_x,
+            values.velocity_y,
+        );
+        self.send_line(action, line, env);
+    }
+
+    /// Write `line` to the process of `action`, starting it with `env` if needed
+    pub fn send_line(&self, action: &Action, line: String, env: &CommandEnv) {
+        let mut state = self.state.lock().unwrap();
+        let State { pipes, threads } = &mut *state;
+        let sender = pipes
+            .entry(action.to_string())
+            .or_insert_with(|| start(threads, action, env));
+        match sender.try_send(line) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                log::warn!("`{action}` is not reading its input, dropping a gesture event")
+            }
+            // The thread feeding the command panicked
+            Err(TrySendError::Disconnected(line)) => {
+                log::error!("The input of `{action}` was lost, restarting it");
+                *sender = start(threads, action, env);
+                let _ = sender.try_send(line);
+            }
+        }
+    }
+
+    /// Stop the processes of commands for which `keep` is false
+    pub fn retain(&self, keep: impl Fn(&str) -> bool) {
+        let mut state = self.state.lock().unwrap();
+        state.pipes.retain(|command, _| keep(command));
+        state.threads.retain(|t| !t.is_finished());
+    }
+
+    /// Stop the processes of commands that no gesture of the active profile of `config` uses
+    pub fn retain_used(&self, config: &Config) {
+        let used: Vec<String> = config
+            .active_gestures()
+            .flat_map(|g| match g {
+                Gesture::Swipe(g) => vec![g.pipe.as_ref(), g.workspace_progress.as_ref()],
+                Gesture::Pinch(g) => vec![g.pipe.as_ref()],
+                _ => vec![],
+            })
+            .flatten()
+            .map(ToString::to_string)
+            .collect();
+        self.retain(|command| used.iter().any(|u| u == command));
+    }
+
+    /// Stop every process and wait for them to exit, as the daemon does when it stops
+    pub fn stop(&self) {
+        let threads = {
+            let mut state = self.state.lock().unwrap();
+            state.pipes.clear();
+            std::mem::take(&mut state.threads)
+        };
+        for thread in threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Start a thread feeding the process of `action`, and return its input
+fn start(
+    threads: &mut Vec<JoinHandle<()>>,
+    action: &Action,
+    env: &CommandEnv,
+) -> SyncSender<String> {
+    let (sender, receiver) = mpsc::sync_channel(BUFFERED_LINES);
+    let (action, env) = (action.clone(), env.clone());
+    threads.push(thread::spawn(move || feed(&action, &env, receiver)));
+    sender
+}
+
+/// Write every line to the process, restarting it whenever it has exited
+fn feed(action: &Action, env: &CommandEnv, lines: Receiver<String>) {
+    let mut child: Option<Child> = None;
+    let mut last_start: Option<Instant> = None;
+    for line in lines {
+        if let Some(c) = &mut child {
+            if let Ok(Some(status)) = c.try_wait() {
+                log::warn!("Pipe `{action}` exited ({status}), restarting it");
+                child = None;
+            }
+        }
+        if child.is_none() {
+            if let Some(wait) = last_start.and_then(|s| RESTART_DELAY.checked_sub(s.elapsed())) {
+                thread::sleep(wait);
+            }
+            last_start = Some(Instant::now());
+            log::debug!("Starting pipe `{action}`");
+            // Config validation keeps D-Bus calls out of pipes
+            let Some(mut command) = action.command(&Values::default()) else {
+                continue;
+            };
+            env.apply(&mut command);
+            match command.stdin(Stdio::piped()).spawn() {
+                Ok(c) => child = Some(c),
+                Err(e) => {
+                    log::error!("Failed to run pipe `{action}`: {e}");
+                    continue;
+                }
+            }
+        }
+        let Some(c) = &mut child else { continue };
+        let written = c
+            .stdin
+            .as_mut()
+            .map(|stdin| writeln!(stdin, "{line}").and_then(|()| stdin.flush()));
+        if let Some(Err(e)) = written {
+            log::warn!("Pipe `{action}` stopped reading ({e}), restarting it");
+            let _ = c.kill();
+            let _ = c.wait();
+            child = None;
+        }
+    }
+    // No gesture uses the command anymore: close its input and give it a moment to exit
+    if let Some(mut c) = child {
+        drop(c.stdin.take());
+        let start = Instant::now();
+        while matches!(c.try_wait(), Ok(None)) {
+            if start.elapsed() > STOP_TIMEOUT {
+                let _ = c.kill();
+                let _ = c.wait();
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
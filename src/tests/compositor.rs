@@ -0,0 +1,92 @@
+use std::io::{Read, Write};
+use std::os::unix::net::UnixListener;
+
+use super::executor::wait_for_metrics;
+use super::{assert_round_trip, parse, parse_error, swipe, temp_dir};
+use crate::compositor::{read_sway_reply, sway_message};
+use crate::environment::CommandEnv;
+use crate::executor::{Executor, ExecutorSettings, Job};
+use crate::gestures::action::{Action, Values};
+
+#[test]
+fn test_compositor_commands() {
+    let dir = temp_dir("ipc");
+    let sway = dir.join("sway.sock");
+    let hyprland = dir.join("hyprland.sock");
+    let (received, commands) = std::sync::mpsc::channel();
+
+    // Fake compositors that accept the first command and reject the second
+    let listener = UnixListener::bind(&sway).unwrap();
+    let sender = received.clone();
+    std::thread::spawn(move || {
+        for (stream, reply) in listener.incoming().zip([
+            r#"[{"success":true}]"#,
+            r#"[{"success":false,"error":"Unknown command"}]"#,
+        ]) {
+            let mut stream = stream.unwrap();
+            let payload = read_sway_reply(&mut stream).unwrap();
+            sender.send(String::from_utf8(payload).unwrap()).unwrap();
+            stream.write_all(&sway_message(reply)).unwrap();
+        }
+    });
+    let listener = UnixListener::bind(&hyprland).unwrap();
+    std::thread::spawn(move || {
+        for (stream, reply) in listener.incoming().zip(["ok", "Invalid dispatcher"]) {
+            let mut stream = stream.unwrap();
+            let mut request = String::new();
+            stream.read_to_string(&mut request).unwrap();
+            received.send(request).unwrap();
+            stream.write_all(reply.as_bytes()).unwrap();
+        }
+    });
+
+    let c = parse(&format!(
+        r#"
+        swipe direction="w" fingers=3 {{
+            start sway="workspace $fingers" socket="{sway}"
+            end sway="bogus" socket="{sway}"
+        }}
+        swipe direction="e" fingers=3 {{
+            start hyprland="dispatch workspace e+1" socket="{hyprland}"
+            end hyprland="dispatch bogus" socket="{hyprland}"
+        }}
+        "#,
+        sway = sway.display(),
+        hyprland = hyprland.display(),
+    ));
+    assert_round_trip(&c);
+
+    let executor = Executor::new(&ExecutorSettings {
+        workers: Some(1),
+        ..Default::default()
+    });
+    let values = Values {
+        fingers: 3,
+        ..Default::default()
+    };
+    for (i, gesture) in c.gestures.iter().enumerate() {
+        let s = swipe(gesture);
+        for action in [&s.start, &s.end] {
+            let Some(Action::Compositor(command)) = action else {
+                panic!("expected a compositor command")
+            };
+            let message = command.render(&values, &CommandEnv::default()).unwrap();
+            executor.submit(Job::message((i, "end"), message));
+        }
+    }
+    let metrics = wait_for_metrics(&executor, |m| m.started == 4 && m.running == 0);
+    assert_eq!((metrics.failed, metrics.errors), (0, 2));
+    assert_eq!(
+        commands.try_iter().collect::<Vec<_>>(),
+        vec![
+            "workspace 3",
+            "bogus",
+            "dispatch workspace e+1",
+            "dispatch bogus"
+        ]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let err = parse_error(r#"swipe direction="n" fingers=3 { end sway="a" hyprland="b"; }"#);
+    assert!(err.contains("only one of"), "{err}");
+}
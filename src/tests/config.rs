@@ -0,0 +1,215 @@
+use std::path::PathBuf;
+
+use super::{assert_round_trip, messages, parse, parse_error, temp_dir};
+use crate::config::{config_dirs, Config, Origin};
+use crate::gestures::hold::Hold;
+use crate::gestures::Gesture;
+use crate::window::WindowFilter;
+
+#[test]
+fn test_profiles() {
+    let mut c = parse(
+        r#"
+        hold fingers=4 action="echo top"
+        global {
+            hold fingers=3 action="echo global"
+        }
+        profile "default" {
+            swipe direction="n" fingers=4 end="echo default"
+        }
+        profile "presentation" {
+            swipe direction="e" fingers=3 end="xdotool key Right"
+            swipe direction="w" fingers=3 end="xdotool key Left"
+        }
+        "#,
+    );
+    assert_eq!(c.active_profile(), "default");
+    assert_eq!(c.active_gestures().count(), 3);
+
+    c.active_profile = "presentation".to_string();
+    let active: Vec<&Gesture> = c.active_gestures().collect();
+    assert_eq!(active.len(), 4);
+    assert_eq!(active[0], &c.global[0]);
+    assert_eq!(active[1], &c.gestures[0]);
+    assert_eq!(active[3], &c.profiles[1].gestures[1]);
+}
+
+#[test]
+fn test_includes() {
+    let dir = temp_dir("includes");
+    std::fs::create_dir_all(dir.join("shared")).unwrap();
+    std::fs::write(
+        dir.join("gestures.kdl"),
+        r#"
+        include "shared/team.kdl"
+        window-query "echo mine"
+        profile "default" {
+            hold fingers=4 action="echo mine"
+        }
+        "#,
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("shared/team.kdl"),
+        r#"
+        include "loop.kdl"
+        window-query "echo team"
+        profile "default" {
+            hold fingers=3 action="echo team"
+        }
+        "#,
+    )
+    .unwrap();
+    std::fs::write(dir.join("shared/loop.kdl"), "").unwrap();
+
+    let c = Config::read_from_file(&dir.join("gestures.kdl")).unwrap();
+    assert_eq!(c.window_query.as_deref(), Some("echo mine"));
+    assert_eq!(c.profiles.len(), 1);
+    assert_eq!(c.active_gestures().count(), 2);
+
+    std::fs::write(dir.join("shared/loop.kdl"), r#"include "../gestures.kdl""#).unwrap();
+    let err = Config::read_from_file(&dir.join("gestures.kdl")).unwrap_err();
+    assert!(err
+        .root_cause()
+        .to_string()
+        .ends_with("is included recursively"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_default_config_dirs() {
+    let vars = |vars: &'static [(&str, &str)]| {
+        move |name: &str| vars.iter().find(|(n, _)| *n == name).map(|(_, v)| v.into())
+    };
+    let (user, system) = config_dirs(vars(&[("HOME", "/home/me")]));
+    assert_eq!(user, Some("/home/me/.config".into()));
+    assert_eq!(system, vec![PathBuf::from("/etc/xdg")]);
+    let (user, system) = config_dirs(vars(&[
+        ("HOME", "/home/me"),
+        ("XDG_CONFIG_HOME", "/cfg"),
+        ("XDG_CONFIG_DIRS", "/a:relative:/b"),
+    ]));
+    assert_eq!(user, Some("/cfg".into()));
+    assert_eq!(system, vec![PathBuf::from("/a"), "/b".into()]);
+    let (user, _) = config_dirs(vars(&[("HOME", ""), ("XDG_CONFIG_HOME", "")]));
+    assert_eq!(user, None);
+
+    let dir = temp_dir("dirs");
+    let (user, system) = (dir.join("home/.config"), dir.join("etc"));
+    std::fs::create_dir_all(user.join("gestures/conf.d")).unwrap();
+    std::fs::create_dir_all(system.join("gestures")).unwrap();
+    let system_dirs = vec![system.clone(), "/nonexistent".into()];
+
+    let err = Config::read_from_dirs(Some(user.clone()), system_dirs.clone()).unwrap_err();
+    let help = err.help().unwrap().to_string();
+    assert!(help.contains(&system.join("gestures/gestures.kdl").display().to_string()));
+    assert!(help.contains("/nonexistent/gestures.kdl"));
+
+    std::fs::write(system.join("gestures/gestures.kdl"), "hold fingers=4").unwrap();
+    std::fs::write(user.join("gestures/conf.d/10-mine.kdl"), "hold fingers=3").unwrap();
+    let c = Config::read_from_dirs(Some(user), system_dirs.clone()).unwrap();
+    assert_eq!(c.gestures.len(), 2);
+
+    let c = Config::read_from_dirs(None, system_dirs).unwrap();
+    assert_eq!(c.gestures.len(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_check_on_load() {
+    let c = parse(
+        r#"
+        swipe direction="any" fingers=3 acceleration=20 mouse-up-delay=500
+        swipe direction="any" fingers=3 update="echo $delta_x"
+        swipe direction="any" fingers=4 update="echo $delta_x"
+        "#,
+    );
+    assert_eq!(
+        messages(&c),
+        vec!["the commands of this swipe run while another binding drags the cursor"]
+    );
+    assert!(c.check().is_ok());
+
+    assert!(parse("swipe direction=\"n\" fingers=-3").check().is_err());
+}
+
+#[test]
+fn test_dump_round_trip() {
+    let c = parse(
+        r#"
+        window-query "echo \"app-id=$(focused)\""
+        template "N" points="0,1 0,0 1,1.5 1,0"
+        global {
+            hold fingers=4 action="rofi"
+        }
+        swipe direction="any" fingers=3 mouse-up-delay=500 acceleration=20
+        swipe direction="nw" fingers=4 speed="fast" min-velocity=100 repeat-every=50 repeat-forward="a" class="(?i)firefox" end="x"
+        pinch direction="counter-clockwise" fingers=2 update="echo $scale"
+        stroke fingers=3 shape="N" action="echo n" min-score=0.75 title="^Terminal$"
+        profile "presentation" {
+            swipe direction="w" fingers=3 end="xdotool key Right"
+        }
+        "#,
+    );
+    let dump = c.to_string();
+    assert_round_trip(&c);
+    // Dumping is stable
+    assert_eq!(
+        Config::parse("dump.kdl", dump.clone()).unwrap().to_string(),
+        dump
+    );
+    assert!(dump.contains("profile \"presentation\" {\n    swipe direction=\"w\""));
+}
+
+#[test]
+fn test_vars() {
+    let c = parse(
+        r#"
+        var name="launcher" value="rofi -show"
+        hold fingers=4 action="${launcher} drun"
+        hold fingers=3 action="${env:CARGO_PKG_NAME} -e sh -c 'echo $${HOME}'"
+        "#,
+    );
+    let actions: Vec<_> = c
+        .gestures
+        .iter()
+        .map(|g| match g {
+            Gesture::Hold(h) => h.action.as_ref().unwrap().to_string(),
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            "rofi -show drun".to_string(),
+            format!("{} -e sh -c 'echo ${{HOME}}'", env!("CARGO_PKG_NAME")),
+        ]
+    );
+    assert_round_trip(&c);
+
+    assert!(parse_error(r#"hold fingers=4 action="${launcher}""#)
+        .contains("variable `launcher` is not defined"));
+
+    // Includes see the variables of the files including them
+    let dir = temp_dir("vars");
+    std::fs::write(dir.join("shared.kdl"), r#"hold fingers=4 action="${term}""#).unwrap();
+    std::fs::write(
+        dir.join("gestures.kdl"),
+        "var name=\"term\" value=\"kitty\"\ninclude \"shared.kdl\"",
+    )
+    .unwrap();
+    let c = Config::read_from_file(&dir.join("gestures.kdl")).unwrap();
+    assert_eq!(
+        c.gestures[0].without_origin(),
+        Gesture::Hold(Hold {
+            fingers: 4,
+            action: Some("kitty".parse().unwrap()),
+            timeout: None,
+            window: WindowFilter::default(),
+            origin: Origin::default(),
+        })
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,103 @@
+use std::io::BufRead;
+use std::process::{Command, Stdio};
+
+use super::executor::wait_for_metrics;
+use super::{assert_round_trip, parse, parse_error, swipe};
+use crate::environment::CommandEnv;
+use crate::executor::{Executor, ExecutorSettings, Job};
+use crate::gestures::action::{Action, Values};
+
+/// A swipe with fingers going `direction` calling `method` of the bus itself over `address`
+fn call(address: &str, direction: &str, method: &str, args: &str) -> String {
+    format!(
+        r#"swipe direction="{direction}" fingers=3 {{
+            end bus="{address}" destination="org.freedesktop.DBus" path="/org/freedesktop/DBus" interface="org.freedesktop.DBus" method="{method}" {args}
+        }}
+        "#
+    )
+}
+
+#[test]
+fn test_dbus_config() {
+    let address = "unix:path=/nonexistent";
+    let c = parse(&call(
+        address,
+        "n",
+        "RequestName",
+        r#"signature="su" "a.b" "0""#,
+    ));
+    assert_round_trip(&c);
+    assert!(matches!(swipe(&c.gestures[0]).end, Some(Action::DBus(_))));
+
+    for (args, message) in [
+        (
+            r#"signature="s""#,
+            "the signature has 1 types for 0 arguments",
+        ),
+        (
+            r#"signature="v" "x""#,
+            "`v` is not a type an argument can have",
+        ),
+    ] {
+        let err = parse_error(&call(address, "n", "Hello", args));
+        assert!(err.contains(message), "{err}");
+    }
+    let err = parse_error(
+        r#"swipe direction="n" fingers=3 { pipe destination="a.b" path="/" method="C"; }"#,
+    );
+    assert!(err.contains("not a D-Bus call"), "{err}");
+}
+
+#[test]
+fn test_dbus_calls() {
+    // A private bus, so the test neither needs nor disturbs a session
+    let Ok(mut daemon) = Command::new("dbus-daemon")
+        .args(["--session", "--nofork", "--print-address"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    else {
+        eprintln!("dbus-daemon is not installed, skipping");
+        return;
+    };
+    let mut address = String::new();
+    std::io::BufReader::new(daemon.stdout.take().unwrap())
+        .read_line(&mut address)
+        .unwrap();
+    let address = address.trim();
+
+    let c = parse(
+        &[
+            call(
+                address,
+                "n",
+                "RequestName",
+                r#"signature="su" "org.example.Gestures$fingers" "0""#,
+            ),
+            call(address, "s", "GetNameOwner", r#""org.example.Gestures3""#),
+            call(address, "e", "GetNameOwner", r#""org.example.Missing""#),
+        ]
+        .concat(),
+    );
+
+    let executor = Executor::new(&ExecutorSettings {
+        workers: Some(1),
+        ..Default::default()
+    });
+    let values = Values {
+        fingers: 3,
+        ..Default::default()
+    };
+    for (i, gesture) in c.gestures.iter().enumerate() {
+        let Some(Action::DBus(call)) = &swipe(gesture).end else {
+            panic!("expected a D-Bus call")
+        };
+        let call = call.render(&values, &CommandEnv::default()).unwrap();
+        executor.submit(Job::call((i, "end"), call));
+    }
+    let metrics = wait_for_metrics(&executor, |m| m.started == 3 && m.running == 0);
+    // The name requested by the first call is owned, the other one isn't
+    assert_eq!((metrics.failed, metrics.errors), (0, 1));
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+}
@@ -0,0 +1,73 @@
+use std::path::Path;
+use std::process::Command;
+
+use super::{assert_round_trip, parse};
+use crate::config::Config;
+use crate::environment::{parse_show_environment, Session};
+use crate::gestures::action::Values;
+use crate::validation::{has_errors, validate};
+
+#[test]
+fn test_environment() {
+    let mut c = parse(
+        r#"
+        var name="bin" value="/opt/bin"
+        environment working-directory="/tmp" {
+            PATH "${bin}:/usr/bin"
+            LANG "C"
+        }
+        "#,
+    );
+    let drop_in = r#"environment { LANG "C.UTF-8"; }"#.to_string();
+    c.merge(Config::parse("drop-in.kdl", drop_in).unwrap());
+    let env = c.environment.resolve(&Session::default());
+    assert_eq!(
+        env.vars,
+        vec![
+            ("PATH".to_string(), "/opt/bin:/usr/bin".to_string()),
+            ("LANG".to_string(), "C.UTF-8".to_string()),
+        ]
+    );
+    assert_round_trip(&c);
+
+    let values = Values {
+        kind: "swipe",
+        phase: "end",
+        fingers: 3,
+        direction: "w",
+        delta_x: -1.5,
+        ..Default::default()
+    };
+    let mut command = Command::new("program");
+    command.envs(values.env());
+    env.apply(&mut command);
+    assert_eq!(command.get_current_dir(), Some(Path::new("/tmp")));
+    let vars: Vec<(String, String)> = command
+        .get_envs()
+        .filter_map(|(k, v)| Some((k.to_str()?.to_string(), v?.to_str()?.to_string())))
+        .collect();
+    for (name, value) in [
+        ("GESTURE_TYPE", "swipe"),
+        ("GESTURE_PHASE", "end"),
+        ("GESTURE_FINGERS", "3"),
+        ("GESTURE_DIRECTION", "w"),
+        ("GESTURE_DELTA_X", "-1.5"),
+        ("LANG", "C.UTF-8"),
+    ] {
+        let var = (name.to_string(), value.to_string());
+        assert!(vars.contains(&var), "{name}={value} in {vars:?}");
+    }
+
+    assert_eq!(
+        parse_show_environment(
+            "HOME=/home/u\nWAYLAND_DISPLAY=wayland-1\nXDG_CURRENT_DESKTOP=$'sway\\'s desk'\n"
+        ),
+        vec![
+            ("WAYLAND_DISPLAY".to_string(), "wayland-1".to_string()),
+            ("XDG_CURRENT_DESKTOP".to_string(), "sway's desk".to_string()),
+        ]
+    );
+
+    let bad = parse(r#"environment { "A=B" "c"; }"#);
+    assert!(has_errors(&validate(&bad)));
+}
@@ -0,0 +1,17 @@
+use crate::event_handler::DragMotion;
+
+#[test]
+fn test_drag_motion() {
+    // Fractions of a pixel add up, in both directions
+    let mut drag = DragMotion::default();
+    let moves: Vec<_> = (0..3).map(|_| drag.pixels(0.4, -0.4)).collect();
+    assert_eq!(moves, vec![(0, 0), (0, 0), (1, -1)]);
+    assert_eq!(drag.pixels(0.7, -0.7), (0, 0));
+    assert_eq!(drag.pixels(0.1, -0.1), (1, -1));
+
+    // Updates with the same timestamp keep the last speed
+    let mut drag = DragMotion::default();
+    assert_eq!(drag.speed(3.0, 4.0, 10_000), 500.0);
+    assert_eq!(drag.speed(1.0, 0.0, 0), 500.0);
+    assert_eq!(drag.speed(1.0, 0.0, 1_000), 1000.0);
+}
@@ -0,0 +1,129 @@
+use std::time::{Duration, Instant};
+
+use super::{blocked, blocked_sh, sh, temp_dir, wait_until};
+use crate::executor::{Executor, ExecutorSettings, Job, Metrics};
+
+/// Wait for the metrics of `executor` to satisfy `done`, failing after 5 seconds
+pub(super) fn wait_for_metrics(executor: &Executor, done: impl Fn(&Metrics) -> bool) -> Metrics {
+    let start = Instant::now();
+    loop {
+        let metrics = executor.metrics();
+        if done(&metrics) {
+            return metrics;
+        }
+        assert!(start.elapsed() < Duration::from_secs(5), "{metrics:?}");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_executor() {
+    let executor = Executor::new(&ExecutorSettings {
+        workers: Some(1),
+        queue_size: Some(1),
+        ..Default::default()
+    });
+    let update = |command| Job::new((0, "update"), command).coalesced(None);
+
+    let (command, running) = blocked();
+    executor.submit(update(command));
+    wait_for_metrics(&executor, |m| m.running == 1);
+    // Updates arriving while the previous one runs replace each other
+    for _ in 0..5 {
+        executor.submit(update(sh("true")));
+    }
+    let metrics = executor.metrics();
+    assert_eq!((metrics.queued, metrics.coalesced), (1, 4));
+
+    executor.submit(Job::new((1, "end"), sh("true")));
+    assert_eq!(executor.metrics().dropped, 1);
+
+    drop(running);
+    let metrics = wait_for_metrics(&executor, |m| m.queued == 0 && m.running == 0);
+    assert_eq!((metrics.submitted, metrics.started), (7, 2));
+
+    // A command holds its worker until it exits
+    let (command, running) = blocked();
+    executor.submit(Job::new((0, "end"), command));
+    wait_for_metrics(&executor, |m| m.running == 1);
+    executor.submit(Job::new((1, "end"), sh("true")));
+    let metrics = executor.metrics();
+    assert_eq!((metrics.running, metrics.queued), (1, 1));
+    drop(running);
+    wait_for_metrics(&executor, |m| m.started == 4 && m.running == 0);
+
+    // A command running out of time is killed along with what it started
+    let dir = temp_dir("executor");
+    let pid_file = dir.join("pid");
+    // Background commands read from /dev/null, unless given the input explicitly
+    let script = format!(
+        "exec 3<&0; read _ <&3 & echo $! > {}; wait",
+        pid_file.display()
+    );
+    let (command, _running) = blocked_sh(&script);
+    executor.submit(Job::new((2, "end"), command).timeout(Some(100)));
+    executor.submit(Job::new((3, "end"), sh("echo failing >&2; exit 3")));
+    let metrics = wait_for_metrics(&executor, |m| m.started == 6 && m.running == 0);
+    assert_eq!((metrics.timed_out, metrics.errors), (1, 2));
+    let stat = format!(
+        "/proc/{}/stat",
+        std::fs::read_to_string(&pid_file).unwrap().trim()
+    );
+    // Gone, or a zombie waiting for init to reap it
+    wait_until("the command's child to be killed", || {
+        std::fs::read_to_string(&stat).map_or(true, |s| s.contains(") Z "))
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_executor_settings() {
+    let executor = Executor::new(&ExecutorSettings {
+        workers: Some(1),
+        ..Default::default()
+    });
+
+    // Workers added on a reload run commands at the same time
+    executor.apply(&ExecutorSettings {
+        workers: Some(2),
+        ..Default::default()
+    });
+    let (first, first_running) = blocked();
+    let (second, second_running) = blocked();
+    executor.submit(Job::new((0, "end"), first));
+    executor.submit(Job::new((1, "end"), second));
+    wait_for_metrics(&executor, |m| m.running == 2);
+    drop((first_running, second_running));
+    wait_for_metrics(&executor, |m| m.running == 0);
+
+    // Only commands that are waited on get the executor's timeout
+    executor.apply(&ExecutorSettings {
+        timeout: Some(50),
+        ..Default::default()
+    });
+    let (end, end_running) = blocked();
+    let (update, _update_running) = blocked();
+    executor.submit(Job::new((0, "end"), end));
+    executor.submit(Job::new((0, "update"), update).coalesced(None));
+    let metrics = wait_for_metrics(&executor, |m| m.timed_out == 1 && m.running == 1);
+    assert_eq!(metrics.started, 4);
+    drop(end_running);
+    wait_for_metrics(&executor, |m| m.running == 0);
+}
+
+#[test]
+fn test_executor_generation() {
+    let executor = Executor::new(&ExecutorSettings::default());
+    let update = |generation, command| {
+        Job::new((0, "update"), command)
+            .coalesced(None)
+            .generation(generation)
+    };
+
+    // The binding that took the same place after a reload doesn't wait for the old one
+    let (command, _running) = blocked();
+    executor.submit(update(0, command));
+    wait_for_metrics(&executor, |m| m.started == 1);
+    executor.submit(update(1, sh("true")));
+    wait_for_metrics(&executor, |m| m.started == 2 && m.running == 1);
+}
@@ -0,0 +1,343 @@
+use super::{assert_round_trip, messages, parse, parse_error, swipe, without_origins};
+use crate::config::{Config, Origin};
+use crate::gestures::action::{Action, Format, Placeholder, Values};
+use crate::gestures::stroke::{builtin_templates, recognize, Stroke, DEFAULT_MIN_SCORE};
+use crate::gestures::swipe::{steps, Swipe, SwipeDir, SwipeSpeed, WorkspaceAxis, FAST_VELOCITY};
+use crate::gestures::Gesture;
+use crate::window::WindowFilter;
+
+#[test]
+fn test_stroke_config() {
+    let c = parse(
+        r#"
+        template "N" points="0,1 0,0 1,1 1,0"
+        stroke fingers=3 shape="N" action="echo n"
+        "#,
+    );
+    assert_eq!(
+        c.templates[0].points.0,
+        vec![(0.0, 1.0), (0.0, 0.0), (1.0, 1.0), (1.0, 0.0)]
+    );
+    assert_eq!(
+        without_origins(&c).gestures,
+        vec![Gesture::Stroke(Stroke {
+            fingers: 3,
+            shape: "N".to_string(),
+            action: Some("echo n".parse().unwrap()),
+            min_score: None,
+            timeout: None,
+            window: WindowFilter::default(),
+            origin: Origin::default(),
+        })]
+    );
+    // Custom templates are offered alongside the builtin ones
+    assert_eq!(c.stroke_templates().len(), builtin_templates().len() + 1);
+}
+
+#[test]
+fn test_recognize() {
+    let templates = builtin_templates();
+    let test_cases = vec![
+        (
+            vec![
+                (0.0, 0.0),
+                (0.0, 50.0),
+                (0.0, 100.0),
+                (40.0, 100.0),
+                (70.0, 100.0),
+            ],
+            "L",
+        ),
+        (
+            vec![(0.0, 0.0), (100.0, 0.0), (0.0, 90.0), (100.0, 100.0)],
+            "Z",
+        ),
+        (
+            vec![
+                (0.0, 0.0),
+                (20.0, 50.0),
+                (50.0, 100.0),
+                (80.0, 50.0),
+                (100.0, 0.0),
+            ],
+            "V",
+        ),
+    ];
+
+    for (path, expected) in test_cases {
+        let (shape, score) = recognize(&path, &templates).unwrap();
+        assert_eq!(shape, expected);
+        assert!(score > DEFAULT_MIN_SCORE, "{shape}: {score}");
+    }
+    assert_eq!(recognize(&[(1.0, 1.0)], &templates), None);
+}
+
+#[test]
+fn test_swipe_velocity() {
+    let c = parse(
+        r#"
+        swipe direction="w" fingers=4 speed="fast" end="echo fast"
+        swipe direction="w" fingers=4 speed="slow" min-velocity=100 end="echo slow"
+        "#,
+    );
+    let (fast, slow) = (swipe(&c.gestures[0]), swipe(&c.gestures[1]));
+    assert_eq!(fast.speed, Some(SwipeSpeed::Fast));
+
+    let test_cases = vec![
+        (50.0, false, false),
+        (200.0, false, true),
+        (FAST_VELOCITY, true, false),
+    ];
+    for (velocity, is_fast, is_slow) in test_cases {
+        assert_eq!(fast.matches_velocity(velocity), is_fast);
+        assert_eq!(slow.matches_velocity(velocity), is_slow);
+    }
+}
+
+#[test]
+fn test_unit_vector() {
+    for dir in [
+        SwipeDir::N,
+        SwipeDir::S,
+        SwipeDir::E,
+        SwipeDir::W,
+        SwipeDir::NE,
+        SwipeDir::SW,
+    ] {
+        let (x, y) = dir.unit_vector();
+        assert!((x.hypot(y) - 1.0).abs() < 1e-9);
+        assert_eq!(SwipeDir::dir(x, y), dir);
+    }
+    assert_eq!(SwipeDir::Any.unit_vector(), (0.0, 0.0));
+}
+
+#[test]
+fn test_action_template() {
+    let values = Values {
+        delta_x: 1.5,
+        delta_y: -2.25,
+        fingers: 3,
+        direction: "ne",
+        ..Default::default()
+    };
+    let render = |s: &str| s.parse::<Format>().unwrap().render(&values);
+
+    assert_eq!(render("$delta_x,$delta_y"), "1.5,-2.25");
+    assert_eq!(
+        render("xdotool mousemove_relative -- {delta_x:.0} {delta_y:.1}"),
+        "xdotool mousemove_relative -- 2 -2.2"
+    );
+    assert_eq!(
+        render("notify-send $fingers-$direction"),
+        "notify-send 3-ne"
+    );
+    assert_eq!(
+        render("echo \\$delta_x \\{scale} $HOME {x}"),
+        "echo $delta_x {scale} $HOME {x}"
+    );
+    assert_eq!(render("echo $scale"), "echo 0");
+
+    let action: Action = "echo {scale} $delta_angle $scale".parse().unwrap();
+    assert_eq!(
+        action.placeholders().collect::<Vec<_>>(),
+        vec![
+            Placeholder::Scale,
+            Placeholder::DeltaAngle,
+            Placeholder::Scale
+        ]
+    );
+    assert!("echo {delta_x:2}".parse::<Action>().is_err());
+
+    // Templates are compiled when the config is read
+    assert!(
+        parse_error(r#"swipe fingers=3 direction="n" end="echo {delta_y:x}""#)
+            .contains("invalid format `x` for `delta_y`")
+    );
+}
+
+#[test]
+fn test_argv_actions() {
+    let c = parse(
+        r#"
+        profile "browser" {
+            swipe direction="w" fingers=4 start="echo start" {
+                end "xdotool" "key" "alt+Right"
+                update "echo" "{delta_x:.0}; rm -rf ~" "$fingers"
+            }
+        }
+        "#,
+    );
+    let s = swipe(&c.profiles[0].gestures[0]);
+    assert!(matches!(s.start, Some(Action::Shell(_))));
+
+    let values = Values {
+        delta_x: 12.4,
+        fingers: 4,
+        ..Default::default()
+    };
+    let program = |action: &Option<Action>| {
+        let command = action.as_ref().unwrap().command(&values).unwrap();
+        command.get_program().to_owned()
+    };
+    let command = s.update.as_ref().unwrap().command(&values).unwrap();
+    assert_eq!(command.get_program(), "echo");
+    // Each argument is passed as is, without a shell splitting or interpreting it
+    assert_eq!(
+        command.get_args().collect::<Vec<_>>(),
+        vec!["12; rm -rf ~", "4"]
+    );
+    assert_eq!(program(&s.end), "xdotool");
+    assert_eq!(program(&s.start), "sh");
+
+    assert_round_trip(&c);
+
+    for (config, message) in [
+        (
+            r#"swipe direction="w" fingers=4 end="echo" { end "echo"; }"#,
+            "`end` is set more than once",
+        ),
+        (
+            r#"hold fingers=4 { end "echo"; }"#,
+            "a hold has no `end` command",
+        ),
+        (
+            r#"hold fingers=4 { action; }"#,
+            "`action` needs at least the program to run",
+        ),
+    ] {
+        let err = parse_error(config);
+        assert!(err.contains(message), "{err}");
+    }
+}
+
+#[test]
+fn test_workspace_swipe() {
+    let c = parse(
+        r#"
+        swipe direction="any" fingers=4 workspace="horizontal" workspace-distance=200
+        swipe direction="any" fingers=3 workspace="vertical" {
+            workspace-progress "workspace-animator" "--smooth"
+        }
+        swipe direction="w" fingers=3 workspace-distance=0 workspace="vertical"
+        "#,
+    );
+    assert_round_trip(&c);
+    let s = swipe(&c.gestures[1]);
+    assert!(matches!(s.workspace_progress, Some(Action::Argv(_))));
+
+    // Left and up go to the next workspace, and the progress stops at a whole switch
+    let horizontal = WorkspaceAxis::Horizontal;
+    assert_eq!(horizontal.progress(-100.0, 30.0, 200), 0.5);
+    assert_eq!(horizontal.progress(500.0, 0.0, 200), -1.0);
+    assert_eq!(WorkspaceAxis::Vertical.progress(-100.0, -75.0, 300), 0.25);
+
+    assert_eq!(
+        messages(&c),
+        vec![
+            "workspace-distance must be greater than 0",
+            "`workspace` follows the swipe both ways, whatever its direction",
+        ]
+    );
+
+    let err = parse_error(
+        r#"swipe direction="any" fingers=3 { workspace-progress sway="workspace next"; }"#,
+    );
+    assert!(err.contains("has to be a program"), "{err}");
+}
+
+#[test]
+fn test_commit_threshold() {
+    let c = parse(
+        r#"
+        swipe direction="w" fingers=3 commit-threshold=100 end="xdotool key alt+Right" {
+            cancel "notify-send" "Cancelled"
+        }
+        swipe direction="any" fingers=4 commit-threshold=50 cancel="true"
+        "#,
+    );
+    assert_round_trip(&c);
+    let (w, any) = (swipe(&c.gestures[0]), swipe(&c.gestures[1]));
+    assert!(matches!(w.cancel, Some(Action::Argv(_))));
+
+    // Only the distance in the swipe's direction counts
+    assert_eq!(w.displacement(-120.0, 40.0), 120.0);
+    assert!(w.committed(-120.0, 40.0));
+    // Swiping left and back again
+    assert!(!w.committed(-10.0, 0.0));
+    assert!(!w.committed(150.0, 0.0));
+    assert!(any.committed(30.0, -40.0));
+    assert!(!any.committed(30.0, -39.0));
+}
+
+#[test]
+fn test_repeat_steps() {
+    // Forward, one step at a time and several at once
+    assert_eq!(steps(0, 49.0, 50), (0, 0));
+    assert_eq!(steps(0, 50.0, 50), (1, 0));
+    assert_eq!(steps(1, 170.0, 50), (2, 0));
+    // Backward
+    assert_eq!(steps(3, 99.0, 50), (0, 2));
+    assert_eq!(steps(1, 60.0, 50), (0, 0));
+    // Crossing where the swipe began
+    assert_eq!(steps(1, -60.0, 50), (0, 2));
+    assert_eq!(steps(-2, 20.0, 50), (2, 0));
+
+    let c = parse(r#"swipe direction="any" fingers=3 repeat-every=50 repeat-forward="a""#);
+    // "any" counts steps in the direction the swipe began in
+    let direction = swipe(&c.gestures[0]).repeat_direction((0.0, -3.0));
+    assert_eq!(direction, SwipeDir::N);
+    let (ux, uy) = direction.unit_vector();
+    assert_eq!(steps(0, 10.0 * ux - 120.0 * uy, 50), (2, 0));
+    assert_eq!(steps(2, 10.0 * ux + 60.0 * uy, 50), (0, 3));
+}
+
+#[test]
+fn test_drag_settings() {
+    let c = parse(
+        r#"
+        swipe direction="any" fingers=3 mouse-up-delay=500 acceleration=20 drag-button="middle" drag-lock=true edge-scroll=20
+        swipe direction="any" fingers=4 drag-lock=true
+        "#,
+    );
+    assert_round_trip(&c);
+    assert_eq!(swipe(&c.gestures[0]).drag_button(), 2);
+    assert_eq!(swipe(&c.gestures[1]).drag_button(), 1);
+
+    assert_eq!(
+        messages(&c),
+        vec!["drag-button, drag-lock and edge-scroll are ignored by a swipe that doesn't drag"]
+    );
+}
+
+#[test]
+fn test_drag_acceleration() {
+    let c = parse(
+        r#"
+        swipe direction="any" fingers=3 mouse-up-delay=500 acceleration=20 acceleration-profile="custom" acceleration-curve="0:0.5 400:1 1500:2.5" unaccelerated=true
+        swipe direction="any" fingers=4 mouse-up-delay=500 acceleration=10 acceleration-profile="custom"
+        "#,
+    );
+    assert_round_trip(&c);
+    let s = swipe(&c.gestures[0]);
+    let curve = s.acceleration_curve.as_ref().unwrap();
+    assert_eq!(curve.factor(0.0), 0.5);
+    assert_eq!(curve.factor(200.0), 0.75);
+    assert_eq!(curve.factor(5000.0), 2.5);
+    assert_eq!(s.drag_delta(1.0, -2.0, 400.0), (2.0, -4.0));
+
+    let flat = Swipe {
+        acceleration: Some(15),
+        ..Default::default()
+    };
+    assert_eq!(flat.drag_delta(1.0, 0.5, 3000.0), (1.5, 0.75));
+
+    assert_eq!(
+        messages(&c),
+        vec!["the custom acceleration profile needs an acceleration-curve"]
+    );
+
+    for curve in ["", "400:1 0:0.5", "fast:2", "0:-1"] {
+        let config = format!("swipe direction=\"any\" fingers=3 acceleration-curve=\"{curve}\"");
+        assert!(Config::parse("test.kdl", config).is_err(), "{curve}");
+    }
+}
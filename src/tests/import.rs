@@ -0,0 +1,52 @@
+use crate::config::Config;
+use crate::gestures::swipe::{Swipe, SwipeDir};
+use crate::gestures::Gesture;
+use crate::import::{import, ImportFormat};
+
+#[test]
+fn test_import() {
+    let m = import(
+        ImportFormat::LibinputGestures,
+        "libinput-gestures.conf",
+        "# Browser\ngesture swipe left 3 xdotool key alt+Right\ngesture pinch in xdotool key ctrl+minus\ngesture swipe up _internal ws_up\n",
+    )
+    .unwrap();
+    assert_eq!(m.problems.len(), 1);
+    let c = Config::parse("imported.kdl", m.kdl).unwrap();
+    assert_eq!(c.gestures.len(), 4);
+    assert_eq!(
+        c.gestures[0].without_origin(),
+        Gesture::Swipe(Box::new(Swipe {
+            direction: SwipeDir::W,
+            fingers: 3,
+            end: Some("xdotool key alt+Right".parse().unwrap()),
+            ..Default::default()
+        }))
+    );
+
+    let m = import(
+        ImportFormat::Fusuma,
+        "config.yml",
+        r#"
+swipe:
+  3:
+    left:
+      command: "xdotool key alt+Right"
+      threshold: 1.5
+    right:
+      sendkey: "LEFTCTRL+T"
+hold:
+  4:
+    command: rofi
+"#,
+    )
+    .unwrap();
+    assert_eq!(m.problems.len(), 1);
+    assert_eq!(
+        m.kdl.lines().skip(1).collect::<Vec<_>>(),
+        vec![
+            r#"swipe direction="w" fingers=3 end="xdotool key alt+Right""#,
+            r#"hold fingers=4 action="rofi""#,
+        ]
+    );
+}
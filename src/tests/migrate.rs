@@ -0,0 +1,35 @@
+use crate::config::Config;
+use crate::migrate::migrate;
+
+#[test]
+fn test_migrate() {
+    let m = migrate(
+        "old.conf",
+        r#"(
+            device: None,
+            gestures: [
+                // Go back
+                Swipe(direction: W, fingers: 3, action: "xdotool key alt+Right"),
+                Pinch(direction: In, fingers: 4, end: "echo \"in\""), // zoom
+                Hold(fingers: 4, action: Some("rofi")),
+                Rotate(fingers: 2, action: "x"),
+            ],
+        )"#,
+    )
+    .unwrap();
+    assert_eq!(m.problems.len(), 1);
+    assert_eq!(
+        m.kdl.lines().skip(1).collect::<Vec<_>>(),
+        vec![
+            "// Go back",
+            r#"swipe direction="w" fingers=3 end="xdotool key alt+Right""#,
+            r#"pinch direction="in" fingers=4 end="echo \"in\"" // zoom"#,
+            r#"hold fingers=4 action="rofi""#,
+            "// migrate-config: Rotate gestures don't exist anymore, left out",
+            r#"// Rotate(fingers: 2, action: "x")"#,
+        ]
+    );
+    assert_eq!(Config::parse("new.kdl", m.kdl).unwrap().gestures.len(), 3);
+
+    assert!(migrate("old.conf", "(gestures: [Swipe(direction: W").is_err());
+}
@@ -1,5 +1,110 @@
-use crate::config::Config;
-use crate::gestures::swipe::SwipeDir;
+mod compositor;
+mod config;
+mod dbus;
+mod environment;
+mod event_handler;
+mod executor;
+mod gestures;
+mod import;
+mod migrate;
+mod pipe;
+mod validation;
+mod window;
+mod xdo_handler;
+
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, Origin};
+use crate::gestures::swipe::{Swipe, SwipeDir};
+use crate::gestures::Gesture;
+use crate::validation::validate;
+
+/// The config read from `text`
+fn parse(text: &str) -> Config {
+    Config::parse("test.kdl", text.to_string()).unwrap()
+}
+
+/// The error reading `text` fails with, with its help and labels
+fn parse_error(text: &str) -> String {
+    let err = Config::parse("test.kdl", text.to_string()).unwrap_err();
+    format!("{err:?}")
+}
+
+/// The messages of the problems validation finds in `c`
+fn messages(c: &Config) -> Vec<String> {
+    validate(c).iter().map(|r| r.to_string()).collect()
+}
+
+fn swipe(g: &Gesture) -> &Swipe {
+    match g {
+        Gesture::Swipe(s) => s,
+        g => panic!("expected a swipe, got {g:?}"),
+    }
+}
+
+/// `c` with the origins of its nodes cleared, to compare it with the same config read from
+/// elsewhere
+fn without_origins(c: &Config) -> Config {
+    let mut c = c.clone();
+    let profiles = c.profiles.iter_mut().flat_map(|p| &mut p.gestures);
+    for g in c.global.iter_mut().chain(&mut c.gestures).chain(profiles) {
+        *g = g.without_origin();
+    }
+    c.executor.origin = Origin::default();
+    c.environment.origin = Origin::default();
+    for var in &mut c.environment.vars {
+        var.origin = Origin::default();
+    }
+    c
+}
+
+/// Check that `c` is read back the same from its dump
+fn assert_round_trip(c: &Config) {
+    let dump = Config::parse("dump.kdl", c.to_string()).unwrap();
+    assert_eq!(without_origins(&dump), without_origins(c));
+}
+
+/// An empty directory of its own for the test `name`
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gestures-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// `script` run with `sh -c`. Tests only use its builtins, so that they don't depend on other
+/// programs.
+fn sh(script: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.args(["-c", script]);
+    command
+}
+
+/// A command that runs until the returned stream is dropped, and doesn't exit on its own
+fn blocked() -> (Command, UnixStream) {
+    blocked_sh("read _ || :")
+}
+
+/// `script` reading from the returned stream, so that `read` waits until the stream is dropped
+fn blocked_sh(script: &str) -> (Command, UnixStream) {
+    let (ours, theirs) = UnixStream::pair().unwrap();
+    let mut command = sh(script);
+    command.stdin(OwnedFd::from(theirs));
+    (command, ours)
+}
+
+/// Wait for `done`, failing with `what` if it takes more than 5 seconds
+fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(start.elapsed() < Duration::from_secs(5), "{what}");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
 
 #[test]
 fn test_config_default() {
@@ -8,7 +113,17 @@ fn test_config_default() {
         c,
         Config {
             // // device: None,
+            window_query: None,
+            executor: Default::default(),
+            environment: Default::default(),
+            vars: vec![],
+            includes: vec![],
+            templates: vec![],
+            global: vec![],
+            profiles: vec![],
             gestures: vec![],
+            active_profile: String::new(),
+            generation: 0,
         }
     );
 }
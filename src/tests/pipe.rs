@@ -0,0 +1,70 @@
+use super::{temp_dir, wait_until};
+use crate::environment::CommandEnv;
+use crate::gestures::action::{Action, Values};
+use crate::pipe::Pipes;
+
+#[test]
+fn test_pipe() {
+    let dir = temp_dir("pipe");
+    let out = dir.join("out");
+    let action: Action = format!("while read -r l; do echo \"$l\"; done > {}", out.display())
+        .parse()
+        .unwrap();
+    let pipes = Pipes::default();
+    pipes.send(
+        &action,
+        "start",
+        &Values {
+            fingers: 3,
+            direction: "any",
+            ..Default::default()
+        },
+        &CommandEnv::default(),
+    );
+    pipes.send(
+        &action,
+        "update",
+        &Values {
+            delta_x: 1.5,
+            delta_y: -2.0,
+            fingers: 3,
+            direction: "e",
+            ..Default::default()
+        },
+        &CommandEnv::default(),
+    );
+    // Stopping closes the input and waits for the command to finish reading
+    pipes.stop();
+    assert_eq!(
+        std::fs::read_to_string(&out).unwrap(),
+        "start 0 0 0 0 3 any 0 0\nupdate 1.5 -2 0 0 3 e 0 0\n"
+    );
+    std::fs::remove_file(&out).unwrap();
+
+    // A command that has exited is started again for the next line
+    let action: Action = format!("read -r l; echo \"$$ $l\" >> {}", out.display())
+        .parse()
+        .unwrap();
+    pipes.send_line(&action, "one".to_string(), &CommandEnv::default());
+    let mut written = String::new();
+    wait_until("the first line to be written", || {
+        written = std::fs::read_to_string(&out).unwrap_or_default();
+        written.ends_with('\n')
+    });
+    let (pid, line) = written.trim_end().split_once(' ').unwrap();
+    assert_eq!(line, "one");
+    // Gone, or a zombie waiting for the pipe to notice
+    let stat = format!("/proc/{pid}/stat");
+    wait_until("the command to exit", || {
+        std::fs::read_to_string(&stat).map_or(true, |s| s.contains(") Z "))
+    });
+    pipes.send_line(&action, "two".to_string(), &CommandEnv::default());
+    pipes.stop();
+    let lines: Vec<String> = std::fs::read_to_string(&out)
+        .unwrap()
+        .lines()
+        .map(|l| l.split_once(' ').unwrap().1.to_string())
+        .collect();
+    assert_eq!(lines, vec!["one", "two"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
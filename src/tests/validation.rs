@@ -0,0 +1,39 @@
+use super::{messages, parse};
+use crate::validation::{has_errors, validate};
+
+#[test]
+fn test_validate() {
+    let c = parse(
+        r#"
+        swipe direction="w" fingers=3 end="xdotool key alt+Right"
+        swipe direction="any" fingers=3 end="echo $scale"
+        hold fingers=0 action=""
+        stroke fingers=3 shape="Q" action="echo q"
+        profile "other" {
+            swipe direction="w" fingers=3 start="echo start"
+        }
+        "#,
+    );
+    assert_eq!(
+        messages(&c),
+        vec![
+            "`end` uses $scale, which is always 0 for a swipe",
+            "hold with 0 fingers can never be triggered",
+            "`action` of this hold is an empty command",
+            "there is no template for the shape `Q`",
+            "`end` of this swipe overlaps another binding",
+        ]
+    );
+    let reports = validate(&c);
+    assert!(has_errors(&reports));
+    assert_eq!(
+        reports[4].help().unwrap().to_string().split(' ').nth(3),
+        Some("test.kdl:2")
+    );
+
+    let c = parse("pinch fingers=2 direction=\"in\" end=\"echo $scale\"");
+    assert!(messages(&c).is_empty());
+
+    let c = parse("hold fingers=4 action=\"rofi\"\nhold fingers=4 action=\"rofi\"");
+    assert_eq!(messages(&c), vec!["duplicate hold"]);
+}
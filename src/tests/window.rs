@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use super::{parse, wait_until};
+use crate::window::{WindowInfo, WindowQuery};
+
+#[test]
+fn test_window_filter() {
+    let c = parse(
+        r#"
+        swipe direction="w" fingers=3 class="(?i)firefox" end="xdotool key alt+Left"
+        swipe direction="w" fingers=3 app-id="spotify" title="^Spotify" end="playerctl previous"
+        hold fingers=4 action="echo hold"
+        "#,
+    );
+    let firefox = WindowInfo {
+        app_id: "Navigator".to_string(),
+        class: "Firefox".to_string(),
+        title: "Mozilla Firefox".to_string(),
+    };
+    let spotify = WindowInfo {
+        app_id: "spotify".to_string(),
+        class: "Spotify".to_string(),
+        title: "Spotify Premium".to_string(),
+    };
+
+    let matches = |w: Option<&WindowInfo>| -> Vec<bool> {
+        c.gestures
+            .iter()
+            .map(|g| g.window().unwrap().matches(w))
+            .collect()
+    };
+    assert_eq!(matches(Some(&firefox)), vec![true, false, true]);
+    assert_eq!(matches(Some(&spotify)), vec![false, true, true]);
+    assert_eq!(matches(None), vec![false, false, true]);
+}
+
+#[test]
+fn test_window_query() {
+    let timeout = Duration::from_millis(200);
+    assert_eq!(
+        WindowInfo::from_command("echo app-id=foot; echo title=~", timeout),
+        Some(WindowInfo {
+            app_id: "foot".to_string(),
+            title: "~".to_string(),
+            ..Default::default()
+        })
+    );
+    assert_eq!(WindowInfo::from_command("exit 1", timeout), None);
+    // A query that hangs is killed rather than holding up the gesture
+    assert_eq!(
+        WindowInfo::from_command("while :; do :; done", timeout),
+        None
+    );
+}
+
+#[test]
+fn test_window_query_thread() {
+    let query = WindowQuery::default();
+    // The first gesture doesn't wait for the query
+    assert_eq!(query.window(Some("echo app-id=foot")), None);
+    wait_until("the query to run", || {
+        query.window(Some("echo app-id=foot")).is_some()
+    });
+    // Nothing the previous command printed is used for another one
+    assert_eq!(query.window(Some("echo app-id=kitty")), None);
+    assert_eq!(query.window(None), None);
+}
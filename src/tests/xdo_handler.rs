@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use crate::xdo_handler::{XDoCommand, XDoHandler};
+
+#[test]
+fn test_drag_lock() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut xdoh = XDoHandler::new(tx);
+    let sent = || rx.try_iter().collect::<Vec<_>>();
+
+    // A locked drag keeps the button held for the next one, until a tap
+    xdoh.mouse_down(2);
+    xdoh.move_mouse_relative(3, -1);
+    xdoh.lock_drag();
+    assert_eq!(
+        sent(),
+        vec![
+            (XDoCommand::MouseDown, 2, 255),
+            (XDoCommand::MoveMouseRelative, 3, -1),
+        ]
+    );
+    xdoh.mouse_down(2);
+    xdoh.lock_drag();
+    assert_eq!(sent(), vec![]);
+    // Releasing a button, or the buttons of a mouse, leave it locked
+    xdoh.button_event(false, true);
+    xdoh.button_event(true, false);
+    xdoh.button_event(false, false);
+    assert_eq!(sent(), vec![]);
+    xdoh.button_event(true, true);
+    xdoh.button_event(false, true);
+    xdoh.release_lock();
+    assert_eq!(sent(), vec![(XDoCommand::MouseUp, 2, 255)]);
+
+    // A drag with another button lets go of the locked one first
+    xdoh.mouse_down(1);
+    xdoh.lock_drag();
+    xdoh.mouse_down(3);
+    assert_eq!(
+        sent(),
+        vec![
+            (XDoCommand::MouseDown, 1, 255),
+            (XDoCommand::MouseUp, 1, 255),
+            (XDoCommand::MouseDown, 3, 255),
+        ]
+    );
+
+    // A cancelled drag lets go after its delay, and isn't locked
+    xdoh.mouse_up_delay(3, 10);
+    assert_eq!(
+        rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+        (XDoCommand::MouseUp, 3, 255)
+    );
+    xdoh.release_lock();
+    assert_eq!(sent(), vec![]);
+}
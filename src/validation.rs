@@ -0,0 +1,509 @@
+use std::path::Path;
+
+use miette::{MietteDiagnostic, Report, Severity};
+
+use crate::config::{Config, Origin};
+use crate::gestures::{
+    action::{Action, Placeholder},
+    pinch::PinchDir,
+    swipe::{AccelProfile, Swipe, SwipeDir},
+    Gesture,
+};
+use crate::window::WindowFilter;
+
+/// Check a parsed config for mistakes that the KDL schema can't express.
+///
+/// Gestures outside of any profile are compared with every profile, but the profiles are not
+/// compared with each other since only one of them is active at a time.
+pub fn validate(config: &Config) -> Vec<Report> {
+    let mut v = Validator {
+        config,
+        reports: Vec::new(),
+    };
+
+    let executor = &config.executor;
+    for (name, value) in [
+        ("workers", executor.workers),
+        ("queue-size", executor.queue_size),
+        ("timeout", executor.timeout),
+    ] {
+        if value == Some(0) {
+            v.report(
+                Severity::Error,
+                &executor.origin,
+                format!("{name} must be greater than 0"),
+                None,
+            );
+        }
+    }
+
+    let environment = &config.environment;
+    for var in &environment.vars {
+        if var.name.is_empty() || var.name.contains(['=', '\0']) {
+            v.report(
+                Severity::Error,
+                &var.origin,
+                format!("`{}` is not a valid environment variable name", var.name),
+                None,
+            );
+        }
+    }
+    if let Some(dir) = &environment.working_directory {
+        if !Path::new(&dir.0).is_dir() {
+            v.report(
+                Severity::Warning,
+                &environment.origin,
+                format!("working directory `{}` does not exist", dir.0),
+                Some("commands will fail to start until it is created".to_string()),
+            );
+        }
+    }
+
+    let global: Vec<&Gesture> = config.global.iter().chain(&config.gestures).collect();
+    for g in &global {
+        v.check_gesture(g);
+    }
+    v.check_overlaps(&[], &global);
+
+    for profile in &config.profiles {
+        let own: Vec<&Gesture> = profile.gestures.iter().collect();
+        for g in &own {
+            v.check_gesture(g);
+        }
+        v.check_overlaps(&global, &own);
+    }
+    v.reports
+}
+
+/// Whether any of the reports is an error rather than a warning
+pub fn has_errors(reports: &[Report]) -> bool {
+    reports
+        .iter()
+        .any(|r| r.severity().unwrap_or(Severity::Error) == Severity::Error)
+}
+
+struct Validator<'a> {
+    config: &'a Config,
+    reports: Vec<Report>,
+}
+
+impl Validator<'_> {
+    fn report(
+        &mut self,
+        severity: Severity,
+        origin: &Origin,
+        message: impl Into<String>,
+        help: Option<String>,
+    ) {
+        let mut diagnostic = MietteDiagnostic::new(message).with_severity(severity);
+        if let Some(help) = help {
+            diagnostic = diagnostic.with_help(help);
+        }
+        self.reports.push(origin.report(diagnostic));
+    }
+
+    fn check_gesture(&mut self, gesture: &Gesture) {
+        let Some(origin) = gesture.origin() else {
+            return;
+        };
+        let kind = gesture.kind();
+
+        let fingers = fingers(gesture);
+        if !(1..=5).contains(&fingers) {
+            self.report(
+                Severity::Error,
+                origin,
+                format!("{kind} with {fingers} fingers can never be triggered"),
+                Some("fingers must be between 1 and 5".to_string()),
+            );
+        }
+
+        for (name, action) in actions(gesture) {
+            let Some(action) = action else { continue };
+            if action.is_empty() {
+                self.report(
+                    Severity::Warning,
+                    origin,
+                    format!("`{name}` of this {kind} is an empty command"),
+                    Some(format!("remove `{name}` if nothing should run")),
+                );
+            }
+            for placeholder in action.placeholders() {
+                if !supplies(gesture, placeholder) {
+                    self.report(
+                        Severity::Warning,
+                        origin,
+                        format!(
+                            "`{name}` uses ${}, which is always {} for a {kind}",
+                            placeholder.name(),
+                            if placeholder == Placeholder::Direction {
+                                "empty"
+                            } else {
+                                "0"
+                            }
+                        ),
+                        None,
+                    );
+                }
+            }
+        }
+
+        if timeout(gesture) == Some(0) {
+            self.report(
+                Severity::Error,
+                origin,
+                "timeout must be greater than 0",
+                Some("leave it out to let the commands run as long as they need".to_string()),
+            );
+        }
+
+        let (max_rate, update, pipe) = match gesture {
+            Gesture::Swipe(g) => (g.max_rate, &g.update, &g.pipe),
+            Gesture::Pinch(g) => (g.max_rate, &g.update, &g.pipe),
+            _ => (None, &None, &None),
+        };
+        if pipe
+            .as_ref()
+            .is_some_and(|p| p.placeholders().next().is_some())
+        {
+            self.report(
+                Severity::Warning,
+                origin,
+                "`pipe` is started only once, so its placeholders are always 0",
+                Some("read the values from its input instead".to_string()),
+            );
+        }
+        match max_rate {
+            Some(0) => self.report(
+                Severity::Error,
+                origin,
+                "max-rate must be greater than 0",
+                None,
+            ),
+            Some(_) if update.is_none() => self.report(
+                Severity::Warning,
+                origin,
+                "max-rate only limits `update`, which is not set",
+                None,
+            ),
+            _ => {}
+        }
+
+        match gesture {
+            Gesture::Swipe(s) => {
+                let drag = s.acceleration.is_some() || s.mouse_up_delay.is_some();
+                if drag && s.direction != SwipeDir::Any {
+                    self.report(
+                        Severity::Warning,
+                        origin,
+                        "acceleration and mouse-up-delay are ignored on this swipe",
+                        Some("they only apply to swipes with direction=\"any\"".to_string()),
+                    );
+                } else if drag && (s.acceleration.is_none() || s.mouse_up_delay.is_none()) {
+                    self.report(
+                        Severity::Warning,
+                        origin,
+                        "dragging needs both acceleration and mouse-up-delay",
+                        None,
+                    );
+                }
+                if s.mouse_up_delay.is_some_and(|d| d < 0) {
+                    self.report(
+                        Severity::Error,
+                        origin,
+                        "mouse-up-delay can't be negative",
+                        None,
+                    );
+                }
+                if let (Some(min), Some(max)) = (s.min_velocity, s.max_velocity) {
+                    if min > max {
+                        self.report(
+                            Severity::Error,
+                            origin,
+                            format!("min-velocity {min} is above max-velocity {max}"),
+                            None,
+                        );
+                    }
+                }
+                let repeats = s.repeat_forward.is_some() || s.repeat_backward.is_some();
+                match s.repeat_every {
+                    Some(0) => self.report(
+                        Severity::Error,
+                        origin,
+                        "repeat-every must be greater than 0",
+                        None,
+                    ),
+                    Some(_) if !repeats => self.report(
+                        Severity::Warning,
+                        origin,
+                        "repeat-every is set but there is nothing to repeat",
+                        Some("add repeat-forward or repeat-backward".to_string()),
+                    ),
+                    None if repeats => self.report(
+                        Severity::Warning,
+                        origin,
+                        "repeat-forward and repeat-backward never run without repeat-every",
+                        None,
+                    ),
+                    _ => {}
+                }
+                if s.workspace_distance == Some(0) {
+                    self.report(
+                        Severity::Error,
+                        origin,
+                        "workspace-distance must be greater than 0",
+                        None,
+                    );
+                }
+                let workspace_settings =
+                    s.workspace_distance.is_some() || s.workspace_progress.is_some();
+                if s.workspace.is_none() && workspace_settings {
+                    self.report(
+                        Severity::Warning,
+                        origin,
+                        "workspace-distance and workspace-progress are ignored without workspace",
+                        Some("add workspace=\"horizontal\" or workspace=\"vertical\"".to_string()),
+                    );
+                }
+                if s.workspace.is_some() && s.direction != SwipeDir::Any {
+                    self.report(
+                        Severity::Warning,
+                        origin,
+                        "`workspace` follows the swipe both ways, whatever its direction",
+                        Some("use direction=\"any\"".to_string()),
+                    );
+                }
+                match (s.acceleration_profile, &s.acceleration_curve) {
+                    (Some(AccelProfile::Custom), None) => self.report(
+                        Severity::Error,
+                        origin,
+                        "the custom acceleration profile needs an acceleration-curve",
+                        Some(
+                            "add points like acceleration-curve=\"0:0.5 400:1 1500:2.5\""
+                                .to_string(),
+                        ),
+                    ),
+                    (profile, Some(_)) if profile != Some(AccelProfile::Custom) => self.report(
+                        Severity::Warning,
+                        origin,
+                        "acceleration-curve is only used by the custom acceleration profile",
+                        Some("add acceleration-profile=\"custom\"".to_string()),
+                    ),
+                    _ => {}
+                }
+                let drag_settings =
+                    s.drag_button.is_some() || s.drag_lock.is_some() || s.edge_scroll.is_some();
+                if !s.is_drag() && drag_settings {
+                    self.report(
+                        Severity::Warning,
+                        origin,
+                        "drag-button, drag-lock and edge-scroll are ignored by a swipe that doesn't drag",
+                        Some("set acceleration and mouse-up-delay with direction=\"any\"".to_string()),
+                    );
+                }
+                if s.is_drag() {
+                    for (name, action) in actions(gesture) {
+                        let phase = matches!(name, "start" | "update" | "end");
+                        if phase && action.is_some_and(|a| !a.is_empty()) {
+                            self.report(
+                                Severity::Warning,
+                                origin,
+                                format!("`{name}` of a dragging swipe is not run on Xorg"),
+                                Some("move it to another swipe with the same fingers".to_string()),
+                            );
+                        }
+                    }
+                }
+            }
+            Gesture::Stroke(s) => {
+                if !self
+                    .config
+                    .stroke_templates()
+                    .iter()
+                    .any(|t| t.name == s.shape)
+                {
+                    self.report(
+                        Severity::Error,
+                        origin,
+                        format!("there is no template for the shape `{}`", s.shape),
+                        Some("define it with a `template` node".to_string()),
+                    );
+                }
+                if s.min_score.is_some_and(|m| !(0.0..=1.0).contains(&m)) {
+                    self.report(
+                        Severity::Error,
+                        origin,
+                        "min-score must be between 0.0 and 1.0",
+                        None,
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Report each gesture in `new` that fires on the same event as one in `earlier` or before
+    /// it in `new`
+    fn check_overlaps(&mut self, earlier: &[&Gesture], new: &[&Gesture]) {
+        for (i, b) in new.iter().enumerate() {
+            let Some(origin) = b.origin() else { continue };
+            for a in earlier.iter().chain(&new[..i]) {
+                let other = a.origin().map(Origin::location).unwrap_or_default();
+                if a.without_origin() == b.without_origin() {
+                    self.report(
+                        Severity::Error,
+                        origin,
+                        format!("duplicate {}", b.kind()),
+                        Some(format!("the same binding is defined at {other}")),
+                    );
+                } else if let Some(message) = drag_conflict(a, b) {
+                    self.report(
+                        Severity::Warning,
+                        origin,
+                        message,
+                        Some(format!("the other binding is at {other}")),
+                    );
+                } else if let Some(phase) = shared_phase(a, b) {
+                    self.report(
+                        Severity::Warning,
+                        origin,
+                        format!("`{phase}` of this {} overlaps another binding", b.kind()),
+                        Some(format!(
+                            "the binding at {other} runs on the same events, both commands will run"
+                        )),
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn fingers(gesture: &Gesture) -> i32 {
+    match gesture {
+        Gesture::Swipe(g) => g.fingers,
+        Gesture::Pinch(g) => g.fingers,
+        Gesture::Hold(g) => g.fingers,
+        Gesture::Stroke(g) => g.fingers,
+        Gesture::None => 0,
+    }
+}
+
+fn timeout(gesture: &Gesture) -> Option<u32> {
+    match gesture {
+        Gesture::Swipe(g) => g.timeout,
+        Gesture::Pinch(g) => g.timeout,
+        Gesture::Hold(g) => g.timeout,
+        Gesture::Stroke(g) => g.timeout,
+        Gesture::None => None,
+    }
+}
+
+/// The commands of a gesture along with the names of their properties
+fn actions(gesture: &Gesture) -> Vec<(&'static str, Option<&Action>)> {
+    match gesture {
+        Gesture::Swipe(g) => vec![
+            ("start", g.start.as_ref()),
+            ("update", g.update.as_ref()),
+            ("end", g.end.as_ref()),
+            ("repeat-forward", g.repeat_forward.as_ref()),
+            ("repeat-backward", g.repeat_backward.as_ref()),
+        ],
+        Gesture::Pinch(g) => vec![
+            ("start", g.start.as_ref()),
+            ("update", g.update.as_ref()),
+            ("end", g.end.as_ref()),
+        ],
+        Gesture::Hold(g) => vec![("action", g.action.as_ref())],
+        Gesture::Stroke(g) => vec![("action", g.action.as_ref())],
+        Gesture::None => vec![],
+    }
+}
+
+/// Whether the event handler passes a value for `placeholder` to the commands of `gesture`
+fn supplies(gesture: &Gesture, placeholder: Placeholder) -> bool {
+    use Placeholder::*;
+    match (gesture, placeholder) {
+        (_, Fingers) => true,
+        (Gesture::Swipe(_), Scale | DeltaAngle) => false,
+        (Gesture::Swipe(_), _) => true,
+        (Gesture::Pinch(_), Scale | DeltaAngle | Direction) => true,
+        _ => false,
+    }
+}
+
+/// Name of the first action that both gestures run on the same event, if any
+fn shared_phase(a: &Gesture, b: &Gesture) -> Option<&'static str> {
+    let same_trigger = fingers(a) == fingers(b)
+        && match (a, b) {
+            (Gesture::Swipe(a), Gesture::Swipe(b)) => {
+                let range = |min: Option<u32>, max: Option<u32>| {
+                    (min.unwrap_or(0), max.unwrap_or(u32::MAX))
+                };
+                let (a_min, a_max) = range(a.min_velocity, a.max_velocity);
+                let (b_min, b_max) = range(b.min_velocity, b.max_velocity);
+                (a.direction == b.direction
+                    || a.direction == SwipeDir::Any
+                    || b.direction == SwipeDir::Any)
+                    && (a.speed.is_none() || b.speed.is_none() || a.speed == b.speed)
+                    && a_min <= b_max
+                    && b_min <= a_max
+            }
+            (Gesture::Pinch(a), Gesture::Pinch(b)) => {
+                a.direction == b.direction
+                    || a.direction == PinchDir::Any
+                    || b.direction == PinchDir::Any
+            }
+            (Gesture::Hold(_), Gesture::Hold(_)) => true,
+            (Gesture::Stroke(a), Gesture::Stroke(b)) => a.shape == b.shape,
+            _ => false,
+        }
+        && windows_overlap(a.window()?, b.window()?);
+    if !same_trigger {
+        return None;
+    }
+
+    let b_actions = actions(b);
+    actions(a)
+        .into_iter()
+        .zip(b_actions)
+        .find(|((_, x), (_, y))| {
+            x.is_some_and(|x| !x.is_empty()) && y.is_some_and(|y| !y.is_empty())
+        })
+        .map(|((name, _), _)| name)
+}
+
+/// Describe how a dragging swipe interferes with another swipe using the same fingers
+fn drag_conflict(a: &Gesture, b: &Gesture) -> Option<&'static str> {
+    let (Gesture::Swipe(a), Gesture::Swipe(b)) = (a, b) else {
+        return None;
+    };
+    if a.fingers != b.fingers || !windows_overlap(&a.window, &b.window) {
+        return None;
+    }
+    let has_commands = |s: &Swipe| {
+        [
+            &s.start,
+            &s.update,
+            &s.end,
+            &s.repeat_forward,
+            &s.repeat_backward,
+        ]
+        .iter()
+        .any(|a| a.as_ref().is_some_and(|a| !a.is_empty()))
+    };
+    match (a.is_drag(), b.is_drag()) {
+        (true, true) => Some("this swipe drags the cursor along with another binding"),
+        (true, false) if has_commands(b) => {
+            Some("the commands of this swipe run while another binding drags the cursor")
+        }
+        (false, true) if has_commands(a) => {
+            Some("this swipe drags the cursor while another binding runs commands")
+        }
+        _ => None,
+    }
+}
+
+/// Filters on different windows are assumed not to match the same window
+fn windows_overlap(a: &WindowFilter, b: &WindowFilter) -> bool {
+    a.is_empty() || b.is_empty() || a == b
+}
@@ -0,0 +1,225 @@
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+
+use knuffel::Decode;
+use regex::Regex;
+
+use crate::kdl::{NodeWriter, ToKdl};
+
+/// How long `window-query` may take before the gesture goes on without it
+pub const QUERY_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How often `window-query` is run in the background while gestures filter on the window
+pub const QUERY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Information about the focused window that gestures can be filtered on
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WindowInfo {
+    pub app_id: String,
+    pub class: String,
+    pub title: String,
+}
+
+impl WindowInfo {
+    /// Ask the configured `window-query` command about the focused window.
+    ///
+    /// The command is run with `sh -c` and prints `app-id=`, `class=` and `title=` lines, any
+    /// of which may be left out. It is killed if it doesn't finish within `timeout`, since
+    /// gestures wait for it.
+    pub fn from_command(cmd: &str, timeout: Duration) -> Option<Self> {
+        let output = match run_with_timeout(cmd, timeout) {
+            Ok(output) => output,
+            Err(e) => {
+                log::warn!("window-query {e}");
+                return None;
+            }
+        };
+
+        let mut info = Self::default();
+        for line in String::from_utf8_lossy(&output).lines() {
+            match line.split_once('=') {
+                Some(("app-id", v)) => info.app_id = v.to_string(),
+                Some(("class", v)) => info.class = v.to_string(),
+                Some(("title", v)) => info.title = v.to_string(),
+                _ => log::debug!("Ignoring window-query output {:?}", line),
+            }
+        }
+        Some(info)
+    }
+}
+
+/// Runs `window-query` on a thread of its own every [`QUERY_INTERVAL`], so that a gesture
+/// beginning uses the window it printed last instead of waiting for it
+#[derive(Debug, Default, Clone)]
+pub struct WindowQuery {
+    shared: Arc<(Mutex<QueryState>, Condvar)>,
+}
+
+#[derive(Debug, Default)]
+struct QueryState {
+    /// The command to run, or none to stop running it
+    command: Option<String>,
+    /// What the command printed last
+    window: Option<WindowInfo>,
+    running: bool,
+}
+
+impl WindowQuery {
+    /// The focused window as `cmd` printed it last, running it from now on. Running no command
+    /// stops the thread.
+    pub fn window(&self, cmd: Option<&str>) -> Option<WindowInfo> {
+        let (state, changed) = &*self.shared;
+        let mut state = state.lock().unwrap();
+        if state.command.as_deref() != cmd {
+            state.command = cmd.map(str::to_string);
+            state.window = None;
+            changed.notify_one();
+        }
+        if cmd.is_some() && !state.running {
+            state.running = true;
+            let query = self.clone();
+            thread::spawn(move || query.run());
+        }
+        state.window.clone()
+    }
+
+    fn run(&self) {
+        let (state, changed) = &*self.shared;
+        let mut state = state.lock().unwrap();
+        while let Some(cmd) = state.command.clone() {
+            drop(state);
+            let window = WindowInfo::from_command(&cmd, QUERY_TIMEOUT);
+            state = self.shared.0.lock().unwrap();
+            // The result of a command that was replaced meanwhile is of no use
+            if state.command.as_ref() == Some(&cmd) {
+                state.window = window;
+                state = changed.wait_timeout(state, QUERY_INTERVAL).unwrap().0;
+            }
+        }
+        state.running = false;
+    }
+}
+
+/// What `cmd` writes to stdout, if it exits successfully within `timeout`
+fn run_with_timeout(cmd: &str, timeout: Duration) -> Result<Vec<u8>, String> {
+    let deadline = Instant::now() + timeout;
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| format!("could not be run: {e}"))?;
+    let mut stdout = child.stdout.take().unwrap();
+    let timed_out = |mut child: std::process::Child| {
+        let _ = killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+        let _ = child.wait();
+        Err(format!(
+            "took longer than {}ms, killed it",
+            timeout.as_millis()
+        ))
+    };
+
+    let mut output = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let mut fds = [PollFd::new(&stdout, PollFlags::POLLIN)];
+        if poll(&mut fds, left.as_millis() as i32).map_err(|e| e.to_string())? == 0 {
+            return timed_out(child);
+        }
+        match stdout.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => output.extend(&buf[..n]),
+            Err(e) => return Err(format!("could not be read: {e}")),
+        }
+    }
+    // Closing stdout usually means it exited, but it may still be about to
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(1)),
+            Ok(None) => return timed_out(child),
+            Err(e) => return Err(e.to_string()),
+        }
+    };
+    if !status.success() {
+        return Err(format!("exited with {status}"));
+    }
+    Ok(output)
+}
+
+/// Regex used in the config
+#[derive(Debug, Clone)]
+pub struct Pattern(pub Regex);
+
+impl FromStr for Pattern {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Regex::new(s).map(Self)
+    }
+}
+
+impl ToKdl for Pattern {
+    fn to_kdl(&self) -> String {
+        self.0.as_str().to_kdl()
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+/// Restricts a gesture to windows matching all of the given patterns
+#[derive(Decode, Debug, Clone, PartialEq, Default)]
+pub struct WindowFilter {
+    #[knuffel(property, str)]
+    pub app_id: Option<Pattern>,
+    #[knuffel(property, str)]
+    pub class: Option<Pattern>,
+    #[knuffel(property, str)]
+    pub title: Option<Pattern>,
+}
+
+impl WindowFilter {
+    pub fn is_empty(&self) -> bool {
+        self.app_id.is_none() && self.class.is_none() && self.title.is_none()
+    }
+
+    /// Add the properties of this filter to a node being written
+    pub fn write_to<'a, 'b>(&self, node: NodeWriter<'a, 'b>) -> NodeWriter<'a, 'b> {
+        node.property("app-id", self.app_id.as_ref())
+            .property("class", self.class.as_ref())
+            .property("title", self.title.as_ref())
+    }
+
+    /// A filter never matches when the focused window is unknown, unless it is empty
+    pub fn matches(&self, window: Option<&WindowInfo>) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let Some(w) = window else {
+            return false;
+        };
+        [
+            (&self.app_id, &w.app_id),
+            (&self.class, &w.class),
+            (&self.title, &w.title),
+        ]
+        .iter()
+        .all(|(p, v)| p.as_ref().map_or(true, |p| p.0.is_match(v)))
+    }
+}
@@ -5,14 +5,33 @@ extern crate timer;
 
 use chrono::Duration;
 use libxdo::XDo;
+use libxdo_sys::{
+    xdo_free, xdo_get_active_window, xdo_get_mouse_location, xdo_get_viewport_dimensions, xdo_new,
+    xdo_t,
+};
+use std::ffi::{CStr, CString};
+use std::sync::atomic::{AtomicI32, Ordering};
 use std::sync::mpsc;
-use std::thread;
+use std::time::Instant;
+use std::{ptr, thread};
 use timer::Timer;
+use x11::xlib;
 
+use crate::window::WindowInfo;
+
+/// Milliseconds between the scroll clicks of a drag at the edge of the screen, and between the
+/// checks of whether it is there
+const EDGE_SCROLL_INTERVAL: i64 = 100;
+
+/// Button held down by a drag, or 0, so that it can be released when the daemon exits
+static HELD_BUTTON: AtomicI32 = AtomicI32::new(0);
+
+#[derive(Debug, PartialEq)]
 pub enum XDoCommand {
     MouseUp,
     MouseDown,
     MoveMouseRelative,
+    Click,
 }
 
 pub struct XDoHandler {
@@ -20,12 +39,21 @@ pub struct XDoHandler {
     timer: Timer,
     guard: Option<timer::Guard>,
     handler_mouse_down: bool,
+    /// Button pressed by the last drag
+    button: i32,
+    /// Whether the button stays held after the drag ended, until a tap or another gesture
+    locked: bool,
+    /// Scroll button clicked while the drag is at the edge of the screen, and its timer
+    scroll: Option<(i32, timer::Guard)>,
+    /// When the pointer was last checked for being at the edge of the screen
+    edge_checked: Option<Instant>,
     pub is_xorg: bool,
+    /// Separate connection used to look up the focused window
+    window_xdo: *mut xdo_t,
 }
 
 pub fn start_handler(is_xorg: bool) -> XDoHandler {
     let (tx, rx) = mpsc::channel();
-    let timer = Timer::new();
     if is_xorg {
         thread::spawn(move || {
             let xdo = XDo::new(None).expect("can not initialize libxdo");
@@ -34,9 +62,14 @@ pub fn start_handler(is_xorg: bool) -> XDoHandler {
                 match command {
                     XDoCommand::MouseDown => {
                         xdo.mouse_down(param1).unwrap();
+                        HELD_BUTTON.store(param1, Ordering::Relaxed);
                     }
                     XDoCommand::MouseUp => {
                         xdo.mouse_up(param1).unwrap();
+                        HELD_BUTTON.store(0, Ordering::Relaxed);
+                    }
+                    XDoCommand::Click => {
+                        xdo.click(param1).unwrap();
                     }
                     XDoCommand::MoveMouseRelative => {
                         xdo.move_mouse_relative(param1, param2).unwrap();
@@ -46,23 +79,50 @@ pub fn start_handler(is_xorg: bool) -> XDoHandler {
         });
     }
 
-    XDoHandler {
-        tx,
-        timer,
-        guard: None,
-        handler_mouse_down: false,
-        is_xorg,
-    }
+    let window_xdo = if is_xorg {
+        unsafe { xdo_new(ptr::null()) }
+    } else {
+        ptr::null_mut()
+    };
+
+    let mut handler = XDoHandler::new(tx);
+    handler.is_xorg = is_xorg;
+    handler.window_xdo = window_xdo;
+    handler
 }
 
 impl XDoHandler {
+    /// A handler sending its commands to `tx`, without a connection to look up windows with
+    pub fn new(tx: mpsc::Sender<(XDoCommand, i32, i32)>) -> Self {
+        XDoHandler {
+            tx,
+            timer: Timer::new(),
+            guard: None,
+            handler_mouse_down: false,
+            button: 1,
+            locked: false,
+            scroll: None,
+            edge_checked: None,
+            is_xorg: false,
+            window_xdo: ptr::null_mut(),
+        }
+    }
+
     pub fn mouse_down(&mut self, button: i32) {
         self.cancel_timer_if_present();
+        if self.locked && self.button == button {
+            // A locked drag goes on with the button it holds
+            self.locked = false;
+            return;
+        }
+        self.release_lock();
         self.tx.send((XDoCommand::MouseDown, button, 255)).unwrap();
+        self.button = button;
         self.handler_mouse_down = true;
     }
 
     pub fn mouse_up_delay(&mut self, button: i32, delay_ms: i64) {
+        self.stop_edge_scroll();
         let tx_clone = self.tx.clone();
         self.guard = Some(self.timer.schedule_with_delay(
             Duration::milliseconds(delay_ms),
@@ -80,10 +140,195 @@ impl XDoHandler {
             .unwrap();
     }
 
+    /// Keep the button held after the drag ended, until [`XDoHandler::release_lock`]
+    pub fn lock_drag(&mut self) {
+        self.stop_edge_scroll();
+        self.locked = true;
+    }
+
+    /// Let go of the button of a locked drag, which a tap or any gesture other than a drag does
+    pub fn release_lock(&mut self) {
+        if self.locked {
+            self.locked = false;
+            self.tx
+                .send((XDoCommand::MouseUp, self.button, 255))
+                .unwrap();
+            self.handler_mouse_down = false;
+        }
+    }
+
+    /// A button of a pointing device was `pressed` or released. A tap or click on the touchpad
+    /// ends a locked drag, while releasing a button or using another device doesn't.
+    pub fn button_event(&mut self, pressed: bool, touchpad: bool) {
+        if pressed && touchpad {
+            self.release_lock();
+        }
+    }
+
+    /// Scroll towards the edge of the screen the pointer is within `zone` pixels of, until it
+    /// moves away or the drag ends. The edges are those of the whole X screen, so the ones
+    /// between monitors don't scroll. The pointer is looked up at most every
+    /// [`EDGE_SCROLL_INTERVAL`] ms, as that takes two round trips to the X server.
+    pub fn edge_scroll(&mut self, zone: u32) {
+        let interval = std::time::Duration::from_millis(EDGE_SCROLL_INTERVAL as u64);
+        if self.edge_checked.is_some_and(|t| t.elapsed() < interval) {
+            return;
+        }
+        self.edge_checked = Some(Instant::now());
+        let Some((x, y, width, height)) = self.pointer_on_screen() else {
+            return;
+        };
+        let zone = zone as i32;
+        // Buttons 4 to 7 scroll up, down, left and right
+        let button = if y < zone {
+            Some(4)
+        } else if y >= height - zone {
+            Some(5)
+        } else if x < zone {
+            Some(6)
+        } else if x >= width - zone {
+            Some(7)
+        } else {
+            None
+        };
+        match button {
+            Some(button) if self.scroll.as_ref().is_some_and(|(b, _)| *b == button) => {}
+            Some(button) => {
+                let tx = self.tx.clone();
+                let guard = self.timer.schedule_repeating(
+                    Duration::milliseconds(EDGE_SCROLL_INTERVAL),
+                    move || {
+                        let _ = tx.send((XDoCommand::Click, button, 255));
+                    },
+                );
+                self.scroll = Some((button, guard));
+            }
+            None => self.scroll = None,
+        }
+    }
+
+    fn stop_edge_scroll(&mut self) {
+        self.scroll = None;
+        self.edge_checked = None;
+    }
+
+    /// Position of the pointer and size of its screen
+    fn pointer_on_screen(&self) -> Option<(i32, i32, i32, i32)> {
+        if self.window_xdo.is_null() {
+            return None;
+        }
+        let (mut x, mut y, mut screen) = (0, 0, 0);
+        let (mut width, mut height) = (0, 0);
+        unsafe {
+            if xdo_get_mouse_location(self.window_xdo, &mut x, &mut y, &mut screen) != 0
+                || xdo_get_viewport_dimensions(self.window_xdo, &mut width, &mut height, screen)
+                    != 0
+            {
+                return None;
+            }
+        }
+        Some((x, y, width as i32, height as i32))
+    }
+
     fn cancel_timer_if_present(&mut self) {
-        if let Some(_) = &self.guard {
+        if self.guard.is_some() {
             self.guard = None;
             self.handler_mouse_down = true;
         }
     }
+
+    /// Look up the `_NET_ACTIVE_WINDOW` and its `WM_CLASS` and `_NET_WM_NAME`.
+    /// The instance part of `WM_CLASS` is reported as the app id.
+    pub fn active_window(&self) -> Option<WindowInfo> {
+        if self.window_xdo.is_null() {
+            return None;
+        }
+        unsafe {
+            let mut window: xlib::Window = 0;
+            if xdo_get_active_window(self.window_xdo, &mut window) != 0 {
+                return None;
+            }
+            let display = (*self.window_xdo).xdpy;
+
+            let mut info = WindowInfo::default();
+            let mut hint = xlib::XClassHint {
+                res_name: ptr::null_mut(),
+                res_class: ptr::null_mut(),
+            };
+            if xlib::XGetClassHint(display, window, &mut hint) != 0 {
+                info.app_id = take_xstring(hint.res_name);
+                info.class = take_xstring(hint.res_class);
+            }
+            info.title = window_title(display, window);
+            Some(info)
+        }
+    }
+}
+
+impl Drop for XDoHandler {
+    fn drop(&mut self) {
+        release_held_button();
+        if !self.window_xdo.is_null() {
+            unsafe { xdo_free(self.window_xdo) };
+        }
+    }
+}
+
+/// Let go of the button a drag holds, so that it isn't left pressed when the daemon exits
+pub fn release_held_button() {
+    let button = HELD_BUTTON.swap(0, Ordering::Relaxed);
+    if button != 0 {
+        if let Ok(xdo) = XDo::new(None) {
+            let _ = xdo.mouse_up(button);
+        }
+    }
+}
+
+/// Read the UTF-8 `_NET_WM_NAME` of a window, falling back to `WM_NAME`
+unsafe fn window_title(display: *mut xlib::Display, window: xlib::Window) -> String {
+    let atom = |name: &str| {
+        let name = CString::new(name).unwrap();
+        xlib::XInternAtom(display, name.as_ptr(), xlib::False)
+    };
+    let mut actual_type = 0;
+    let mut actual_format = 0;
+    let mut items = 0;
+    let mut bytes_after = 0;
+    let mut data: *mut u8 = ptr::null_mut();
+    let status = xlib::XGetWindowProperty(
+        display,
+        window,
+        atom("_NET_WM_NAME"),
+        0,
+        1024,
+        xlib::False,
+        atom("UTF8_STRING"),
+        &mut actual_type,
+        &mut actual_format,
+        &mut items,
+        &mut bytes_after,
+        &mut data,
+    );
+    if status == xlib::Success as i32 && !data.is_null() {
+        let bytes = std::slice::from_raw_parts(data, items as usize);
+        let title = String::from_utf8_lossy(bytes).into_owned();
+        xlib::XFree(data.cast());
+        return title;
+    }
+
+    let mut name = ptr::null_mut();
+    if xlib::XFetchName(display, window, &mut name) != 0 {
+        return take_xstring(name);
+    }
+    String::new()
+}
+
+/// Copy a string allocated by Xlib and free it
+unsafe fn take_xstring(s: *mut std::os::raw::c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    let owned = CStr::from_ptr(s).to_string_lossy().into_owned();
+    xlib::XFree(s.cast());
+    owned
 }